@@ -1,3 +1,5 @@
+mod options;
+pub use self::options::*;
 mod run;
 pub use self::run::*;
 mod list;
@@ -12,3 +14,13 @@ mod logs;
 pub use self::logs::*;
 mod serve;
 pub use self::serve::*;
+mod volume;
+pub use self::volume::*;
+mod routes;
+pub use self::routes::*;
+mod inspect;
+pub use self::inspect::*;
+mod wait;
+pub use self::wait::*;
+mod attach;
+pub use self::attach::*;
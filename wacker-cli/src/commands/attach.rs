@@ -0,0 +1,186 @@
+use crate::commands::{parse_detach_keys, DetachKeys};
+use anyhow::{anyhow, bail, Result};
+use clap::Parser;
+use std::io::{stderr, stdin, stdout, IsTerminal, Read, Write};
+use std::{process, thread};
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::{wrappers::ReceiverStream, StreamExt};
+use tonic::transport::Channel;
+use wacker::{attach_request, attach_response, AttachProgram, AttachRequest, Client};
+
+pub const DEFAULT_DETACH_KEYS: &str = "ctrl-p,ctrl-q";
+
+#[derive(Parser)]
+pub struct AttachCommand {
+    /// Program ID
+    #[arg(required = true)]
+    id: String,
+    /// Do not forward stdin to the program
+    #[arg(long)]
+    no_stdin: bool,
+    /// Key sequence for detaching from the program
+    #[arg(long, value_name = "KEYS", default_value = DEFAULT_DETACH_KEYS, value_parser = parse_detach_keys)]
+    detach_keys: DetachKeys,
+}
+
+impl AttachCommand {
+    /// Executes the command.
+    pub async fn execute(self, client: Client<Channel>) -> Result<()> {
+        let request = AttachRequest {
+            request: Some(attach_request::Request::Program(AttachProgram {
+                id: self.id,
+                stdin: !self.no_stdin,
+            })),
+        };
+        // Keys are sent as they are typed when attached to a program, so
+        // that the detach keys work.
+        let terminal = TerminalMode::enable(!self.no_stdin);
+        let exit_code = attach(client, request, !self.no_stdin, self.detach_keys).await;
+        drop(terminal);
+        match exit_code? {
+            Some(code) => process::exit(code),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Attaches to a program until it exits, and returns its exit code, or
+/// `None` if the user detached from it.
+pub async fn attach(
+    mut client: Client<Channel>,
+    request: AttachRequest,
+    forward_stdin: bool,
+    detach_keys: DetachKeys,
+) -> Result<Option<i32>> {
+    let (tx, rx) = mpsc::channel(128);
+    tx.send(request).await?;
+    let (detach_tx, mut detach_rx) = oneshot::channel();
+    if forward_stdin {
+        // Reading stdin blocks, so do it in its own thread.
+        thread::spawn(move || read_stdin(tx, detach_keys, detach_tx));
+    } else {
+        drop(tx);
+    }
+
+    let mut responses = match client.attach(ReceiverStream::new(rx)).await {
+        Ok(resp) => resp.into_inner(),
+        Err(err) => bail!(err.message().to_string()),
+    };
+    loop {
+        let resp = tokio::select! {
+            resp = responses.next() => resp,
+            Ok(()) = &mut detach_rx => return Ok(None),
+        };
+        let Some(resp) = resp else {
+            bail!("lost the connection to the program");
+        };
+        match resp.map_err(|err| anyhow!(err.message().to_string()))?.response {
+            Some(attach_response::Response::Stdout(bytes)) => {
+                let mut stdout = stdout().lock();
+                stdout.write_all(&bytes)?;
+                stdout.flush()?;
+            }
+            Some(attach_response::Response::Stderr(bytes)) => {
+                let mut stderr = stderr().lock();
+                stderr.write_all(&bytes)?;
+                stderr.flush()?;
+            }
+            // Programs that were stopped or trapped have no exit code.
+            Some(attach_response::Response::Exit(exit)) => return Ok(Some(exit.exit_code.unwrap_or(1))),
+            Some(attach_response::Response::Id(_)) | None => {}
+        }
+    }
+}
+
+/// Sends stdin to the program until it reaches EOF, which closes the stdin of
+/// a program run by this client, or until the detach keys are typed.
+fn read_stdin(tx: mpsc::Sender<AttachRequest>, DetachKeys(detach_keys): DetachKeys, detach: oneshot::Sender<()>) {
+    let mut stdin = stdin().lock();
+    let mut buf = vec![0; 8192];
+    let mut matched = 0;
+    loop {
+        let n = match stdin.read(&mut buf) {
+            Ok(0) | Err(_) => return,
+            Ok(n) => n,
+        };
+
+        let mut data = Vec::with_capacity(n);
+        let mut detached = false;
+        for &byte in &buf[..n] {
+            if detach_keys.is_empty() {
+                data.push(byte);
+                continue;
+            }
+            // Keys that turn out not to be part of the sequence are sent
+            // to the program.
+            if byte != detach_keys[matched] {
+                data.extend_from_slice(&detach_keys[..matched]);
+                matched = 0;
+            }
+            if byte == detach_keys[matched] {
+                matched += 1;
+                if matched == detach_keys.len() {
+                    detached = true;
+                    break;
+                }
+            } else {
+                data.push(byte);
+            }
+        }
+
+        if !data.is_empty() {
+            let request = AttachRequest {
+                request: Some(attach_request::Request::Stdin(data)),
+            };
+            if tx.blocking_send(request).is_err() {
+                return;
+            }
+        }
+        if detached {
+            // Keep the request stream open, so that detaching doesn't close
+            // the stdin of the program.
+            let _ = detach.send(());
+            loop {
+                thread::park();
+            }
+        }
+    }
+}
+
+/// Switches the terminal to non-canonical mode without flow control, so that
+/// keys are sent as they are typed, and restores it when dropped.
+pub struct TerminalMode(libc::termios);
+
+impl TerminalMode {
+    pub fn enable(enable: bool) -> Option<Self> {
+        if !enable || !stdin().is_terminal() {
+            return None;
+        }
+        // SAFETY: termios is a plain C struct, and it's initialized by
+        // tcgetattr before being used.
+        unsafe {
+            let mut termios: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+                return None;
+            }
+            let original = termios;
+            termios.c_lflag &= !libc::ICANON;
+            termios.c_iflag &= !libc::IXON;
+            termios.c_cc[libc::VMIN] = 1;
+            termios.c_cc[libc::VTIME] = 0;
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) != 0 {
+                return None;
+            }
+            Some(Self(original))
+        }
+    }
+}
+
+impl Drop for TerminalMode {
+    fn drop(&mut self) {
+        // SAFETY: the termios was returned by tcgetattr.
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.0);
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use crate::commands::STATUS;
+use anyhow::{bail, Result};
+use clap::Parser;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use tonic::transport::Channel;
+use wacker::{
+    utils::format_rfc3339, Client, InspectRequest, ProgramDetails, LOG_DRIVER_FILE, LOG_DRIVER_JSON_LINES,
+    LOG_DRIVER_SYSLOG, PROGRAM_TYPE_CLI, PROGRAM_TYPE_HTTP, RESTART_POLICY_ALWAYS, RESTART_POLICY_NO,
+    RESTART_POLICY_ON_FAILURE, RESTART_POLICY_UNLESS_STOPPED,
+};
+
+#[derive(Parser)]
+pub struct InspectCommand {
+    /// Program IDs
+    #[arg(required = true, value_name = "IDs")]
+    ids: Vec<String>,
+}
+
+fn to_json(program: ProgramDetails) -> Value {
+    let program_type = match program.program_type {
+        PROGRAM_TYPE_CLI => "cli",
+        PROGRAM_TYPE_HTTP => "http",
+        _ => "unknown",
+    };
+    let limits = program.limits.unwrap_or_default();
+    let restart = program.restart.unwrap_or_default();
+    let log = program.log.unwrap_or_default();
+    let rate_limit = program.rate_limit.unwrap_or_default();
+    let log_driver = match log.driver {
+        0 => None,
+        LOG_DRIVER_FILE => Some("file"),
+        LOG_DRIVER_JSON_LINES => Some("json-lines"),
+        LOG_DRIVER_SYSLOG => Some("syslog"),
+        _ => Some("unknown"),
+    };
+    let restart_policy = match restart.policy {
+        RESTART_POLICY_NO => "no",
+        RESTART_POLICY_ON_FAILURE => "on-failure",
+        RESTART_POLICY_ALWAYS => "always",
+        RESTART_POLICY_UNLESS_STOPPED => "unless-stopped",
+        _ => "unknown",
+    };
+    // Unset values are 0 in the response.
+    let optional = |value: u64| (value > 0).then_some(value);
+
+    json!({
+        "id": program.id,
+        "path": program.path,
+        "type": program_type,
+        "status": STATUS.get(&program.status).unwrap_or(&"Unknown"),
+        "address": (!program.addr.is_empty()).then_some(program.addr),
+        "routes": program.routes.into_iter().map(|route| json!({
+            "host": (!route.host.is_empty()).then_some(route.host),
+            "path": route.path,
+        })).collect::<Vec<_>>(),
+        "http2_only": program.http2_only,
+        "tls": (!program.tls_cert.is_empty()).then(|| json!({
+            "cert": program.tls_cert,
+            "key": program.tls_key,
+        })),
+        "args": program.args,
+        "env": program.env.into_iter().collect::<BTreeMap<_, _>>(),
+        "inherit_env": program.inherit_env,
+        "labels": program.labels.into_iter().collect::<BTreeMap<_, _>>(),
+        "mounts": {
+            "dirs": program.dirs.into_iter().map(|dir| json!({
+                "host_path": dir.host_path,
+                "guest_path": dir.guest_path,
+                "read_only": dir.read_only,
+            })).collect::<Vec<_>>(),
+            "volumes": program.volumes.into_iter().map(|volume| json!({
+                "name": volume.name,
+                "guest_path": volume.guest_path,
+                "read_only": volume.read_only,
+            })).collect::<Vec<_>>(),
+        },
+        "limits": {
+            "memory": optional(limits.memory),
+            "table_elements": optional(limits.table_elements as u64),
+            "instances": optional(limits.instances as u64),
+            "fuel": optional(limits.fuel),
+            "timeout": optional(program.timeout),
+            "request_fuel": optional(program.request_fuel),
+            "request_timeout": optional(program.request_timeout),
+            "max_connections": optional(program.max_connections as u64),
+            "max_concurrent_requests": optional(program.max_concurrent_requests as u64),
+            "request_backlog": optional(program.request_backlog as u64),
+        },
+        "rate_limit": (rate_limit.rate > 0).then(|| json!({
+            "rate": rate_limit.rate,
+            "burst": optional(rate_limit.burst as u64),
+            "header": (!rate_limit.header.is_empty()).then_some(rate_limit.header),
+        })),
+        "restart_policy": {
+            "name": restart_policy,
+            "max_retries": optional(restart.max_retries as u64),
+        },
+        "log": {
+            "max_size": optional(log.max_size),
+            "max_files": optional(log.max_files as u64),
+            "driver": log_driver,
+            "syslog_address": (!log.syslog_address.is_empty()).then_some(log.syslog_address),
+        },
+        "restart_count": program.restart_count,
+        "exit_code": program.exit_code,
+        "error": (!program.error.is_empty()).then_some(program.error),
+        "fuel_consumed": program.fuel_consumed,
+        "created_at": format_rfc3339(program.created_at),
+        "started_at": format_rfc3339(program.started_at),
+        "finished_at": format_rfc3339(program.finished_at),
+        "module_hash": (!program.module_hash.is_empty()).then_some(program.module_hash),
+        "engine_config": program.engine_config.into_iter().collect::<BTreeMap<_, _>>(),
+    })
+}
+
+impl InspectCommand {
+    /// Executes the command.
+    pub async fn execute(self, mut client: Client<Channel>) -> Result<()> {
+        let response = match client.inspect(InspectRequest { ids: self.ids }).await {
+            Ok(resp) => resp.into_inner(),
+            Err(err) => bail!(err.message().to_string()),
+        };
+
+        let programs: Vec<Value> = response.programs.into_iter().map(to_json).collect();
+        println!("{}", serde_json::to_string_pretty(&programs)?);
+
+        Ok(())
+    }
+}
@@ -7,7 +7,10 @@ use tabled::{
     Table, Tabled,
 };
 use tonic::transport::Channel;
-use wacker::{Client, PROGRAM_STATUS_ERROR, PROGRAM_STATUS_FINISHED, PROGRAM_STATUS_RUNNING, PROGRAM_STATUS_STOPPED};
+use wacker::{
+    Client, PROGRAM_STATUS_ERROR, PROGRAM_STATUS_FINISHED, PROGRAM_STATUS_FUEL_EXHAUSTED, PROGRAM_STATUS_RESTARTING,
+    PROGRAM_STATUS_RUNNING, PROGRAM_STATUS_STOPPED, PROGRAM_STATUS_TIMED_OUT,
+};
 
 #[derive(Parser)]
 pub struct ListCommand {}
@@ -22,14 +25,21 @@ struct Program {
     status: &'static str,
     #[tabled(rename = "ADDRESS")]
     address: String,
+    #[tabled(rename = "RESTARTS")]
+    restarts: u32,
+    #[tabled(rename = "EXIT CODE")]
+    exit_code: String,
 }
 
-static STATUS: LazyLock<HashMap<u32, &'static str>> = LazyLock::new(|| {
+pub static STATUS: LazyLock<HashMap<u32, &'static str>> = LazyLock::new(|| {
     HashMap::from([
         (PROGRAM_STATUS_RUNNING, "Running"),
         (PROGRAM_STATUS_FINISHED, "Finished"),
         (PROGRAM_STATUS_ERROR, "Error"),
         (PROGRAM_STATUS_STOPPED, "Stopped"),
+        (PROGRAM_STATUS_FUEL_EXHAUSTED, "OutOfFuel"),
+        (PROGRAM_STATUS_TIMED_OUT, "TimedOut"),
+        (PROGRAM_STATUS_RESTARTING, "Restarting"),
     ])
 });
 
@@ -47,6 +57,8 @@ impl ListCommand {
                 path: res.path,
                 status: STATUS.get(&res.status).unwrap_or(&"Unknown"),
                 address: res.addr,
+                restarts: res.restart_count,
+                exit_code: res.exit_code.map(|code| code.to_string()).unwrap_or_default(),
             })
         }
 
@@ -1,15 +1,28 @@
+use crate::commands::parse_label;
 use anyhow::{bail, Result};
 use clap::Parser;
-use std::io::{stdout, Write};
+use hashbrown::HashMap;
+use std::io::{stderr, stdout, IsTerminal, Write};
 use tokio_stream::StreamExt;
 use tonic::transport::Channel;
-use wacker::{Client, LogRequest};
+use wacker::{utils::parse_time, Client, LogRequest, LOG_STREAM_STDERR, LOG_STREAM_STDOUT};
+
+/// The ANSI colors of the program IDs, red is left out for errors.
+const COLORS: [u8; 5] = [32, 33, 34, 35, 36];
 
 #[derive(Parser)]
 pub struct LogsCommand {
-    /// Program ID
-    #[arg(required = true)]
-    id: String,
+    /// Program IDs, the logs of several programs are merged by time
+    #[arg(required_unless_present = "labels", value_name = "IDs")]
+    ids: Vec<String>,
+
+    /// Also show the logs of the programs with this label, can be specified multiple times
+    #[arg(short, long = "label", value_name = "KEY=VALUE", value_parser = parse_label)]
+    labels: Vec<(String, String)>,
+
+    /// Don't color the program IDs in the logs of several programs
+    #[arg(long)]
+    no_color: bool,
 
     /// Follow log output
     #[arg(short, long)]
@@ -18,6 +31,80 @@ pub struct LogsCommand {
     /// Number of lines to show from the end of the logs
     #[arg(short = 'n', long, value_name = "n")]
     tail: Option<u32>,
+
+    /// Only show the logs written to stdout
+    #[arg(long, conflicts_with = "stderr")]
+    stdout: bool,
+
+    /// Only show the logs written to stderr
+    #[arg(long)]
+    stderr: bool,
+
+    /// Show logs since a time, e.g. 2024-01-01T12:00:00Z, a Unix timestamp, or a duration before now such as 10m
+    #[arg(long, value_name = "TIME", value_parser = parse_time)]
+    since: Option<u64>,
+
+    /// Show logs before a time, in the same formats as --since
+    #[arg(long, value_name = "TIME", value_parser = parse_time)]
+    until: Option<u64>,
+
+    /// Prefix every line with the time it was written
+    #[arg(short, long)]
+    timestamps: bool,
+
+    /// Only show the lines matching a regular expression, filtered by the daemon
+    #[arg(long, value_name = "PATTERN")]
+    grep: Option<String>,
+
+    /// Match the --grep pattern as a plain substring
+    #[arg(short = 'F', long, requires = "grep")]
+    fixed_strings: bool,
+
+    /// Show the lines not matching the --grep pattern instead
+    #[arg(short = 'v', long, requires = "grep")]
+    invert_match: bool,
+}
+
+/// Writes the logs to stdout and stderr as they are, it may not be valid
+/// UTF-8. The lines of several programs are prefixed with the program ID.
+struct LogPrinter {
+    prefix: bool,
+    color: bool,
+    colors: HashMap<String, u8>,
+    /// Whether the next content of a program and a stream starts a new line.
+    line_start: HashMap<(String, u32), bool>,
+}
+
+impl LogPrinter {
+    fn print(&mut self, id: String, stream: u32, content: &[u8]) -> Result<()> {
+        let mut out: Box<dyn Write> = match stream {
+            LOG_STREAM_STDERR => Box::new(stderr().lock()),
+            _ => Box::new(stdout().lock()),
+        };
+        if !self.prefix {
+            out.write_all(content)?;
+            return Ok(out.flush()?);
+        }
+
+        let next = COLORS[self.colors.len() % COLORS.len()];
+        let prefix = match self.color {
+            true => format!(
+                "\x1b[{}m{} |\x1b[0m ",
+                self.colors.entry(id.clone()).or_insert(next),
+                id
+            ),
+            false => format!("{} | ", id),
+        };
+        let line_start = self.line_start.entry((id, stream)).or_insert(true);
+        for line in content.split_inclusive(|byte| *byte == b'\n') {
+            if *line_start {
+                out.write_all(prefix.as_bytes())?;
+            }
+            out.write_all(line)?;
+            *line_start = line.ends_with(b"\n");
+        }
+        Ok(out.flush()?)
+    }
 }
 
 impl LogsCommand {
@@ -25,17 +112,36 @@ impl LogsCommand {
     pub async fn execute(self, mut client: Client<Channel>) -> Result<()> {
         match client
             .logs(LogRequest {
-                id: self.id,
+                id: String::new(),
+                ids: self.ids.clone(),
+                labels: self.labels.iter().cloned().collect(),
                 follow: self.follow,
                 tail: self.tail.unwrap_or(0),
+                stream: match (self.stdout, self.stderr) {
+                    (true, _) => LOG_STREAM_STDOUT,
+                    (_, true) => LOG_STREAM_STDERR,
+                    _ => 0,
+                },
+                since: self.since.unwrap_or_default(),
+                until: self.until.unwrap_or_default(),
+                timestamps: self.timestamps,
+                grep: self.grep.clone().unwrap_or_default(),
+                fixed_strings: self.fixed_strings,
+                invert_match: self.invert_match,
             })
             .await
         {
             Ok(resp) => {
+                let mut printer = LogPrinter {
+                    prefix: self.ids.len() > 1 || !self.labels.is_empty(),
+                    color: !self.no_color && stdout().is_terminal() && stderr().is_terminal(),
+                    colors: HashMap::new(),
+                    line_start: HashMap::new(),
+                };
                 let mut resp = resp.into_inner();
                 while let Some(item) = resp.next().await {
-                    print!("{}", item.unwrap().content);
-                    stdout().flush()?;
+                    let item = item.unwrap();
+                    printer.print(item.id, item.stream, &item.content)?;
                 }
                 Ok(())
             }
@@ -0,0 +1,216 @@
+use anyhow::{anyhow, bail, Context, Result};
+use clap::Args;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{absolute, PathBuf};
+use wacker::{
+    utils::{parse_log_driver, parse_size},
+    DirMount, LogConfig, ResourceLimits, RestartPolicy, Route, VolumeMount, RESTART_POLICY_ALWAYS, RESTART_POLICY_NO,
+    RESTART_POLICY_ON_FAILURE, RESTART_POLICY_UNLESS_STOPPED,
+};
+
+#[derive(Args)]
+pub struct LimitOptions {
+    /// Maximum size of each linear memory, e.g. 64M
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    memory: Option<u64>,
+    /// Maximum number of elements of each table
+    #[arg(long, value_name = "N")]
+    max_table_elements: Option<u32>,
+    /// Maximum number of instances
+    #[arg(long, value_name = "N")]
+    max_instances: Option<u32>,
+    /// Fuel budget of the program
+    #[arg(long, value_name = "N")]
+    fuel: Option<u64>,
+}
+
+impl From<LimitOptions> for ResourceLimits {
+    fn from(options: LimitOptions) -> Self {
+        Self {
+            memory: options.memory.unwrap_or_default(),
+            table_elements: options.max_table_elements.unwrap_or_default(),
+            instances: options.max_instances.unwrap_or_default(),
+            fuel: options.fuel.unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct LogOptions {
+    /// Rotate the log file once it reaches this size, e.g. 10M, defaults to the daemon setting
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    log_max_size: Option<u64>,
+    /// Maximum number of log files kept, including the current one, defaults to the daemon setting
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    log_max_files: Option<u32>,
+    /// Where the output goes: file, json-lines or syslog, defaults to the daemon setting
+    #[arg(long, value_name = "DRIVER", value_parser = parse_log_driver)]
+    log_driver: Option<u32>,
+    /// Path of the unix datagram socket of the syslog log driver, defaults to the daemon setting
+    #[arg(long, value_name = "PATH")]
+    syslog_address: Option<String>,
+}
+
+impl From<LogOptions> for LogConfig {
+    fn from(options: LogOptions) -> Self {
+        Self {
+            max_size: options.log_max_size.unwrap_or_default(),
+            max_files: options.log_max_files.unwrap_or_default(),
+            driver: options.log_driver.unwrap_or_default(),
+            syslog_address: options.syslog_address.unwrap_or_default(),
+        }
+    }
+}
+
+/// Parses a restart policy, e.g. `always` or `on-failure:5`.
+pub fn parse_restart_policy(restart: &str) -> Result<RestartPolicy> {
+    let (policy, max_retries) = match restart.split_once(':') {
+        Some(("on-failure", max_retries)) => (RESTART_POLICY_ON_FAILURE, max_retries.parse()?),
+        Some(_) => bail!("only the on-failure restart policy accepts the maximum retries"),
+        None => match restart {
+            "no" => (RESTART_POLICY_NO, 0),
+            "on-failure" => (RESTART_POLICY_ON_FAILURE, 0),
+            "always" => (RESTART_POLICY_ALWAYS, 0),
+            "unless-stopped" => (RESTART_POLICY_UNLESS_STOPPED, 0),
+            _ => bail!(
+                "invalid restart policy {}, expected no, on-failure[:max-retries], always or unless-stopped",
+                restart
+            ),
+        },
+    };
+    Ok(RestartPolicy { policy, max_retries })
+}
+
+/// Splits a mount in the form of `SOURCE[:TARGET][:ro|rw]`.
+fn split_mount(mount: &str) -> Result<(&str, Option<&str>, bool)> {
+    let mut parts: Vec<&str> = mount.split(':').collect();
+    let read_only = parts.len() > 1 && parts[parts.len() - 1] == "ro";
+    if parts.len() > 1 && matches!(parts[parts.len() - 1], "ro" | "rw") {
+        parts.pop();
+    }
+    match parts.as_slice() {
+        [source] if !source.is_empty() => Ok((source, None, read_only)),
+        [source, target] if !source.is_empty() && !target.is_empty() => Ok((source, Some(target), read_only)),
+        _ => bail!("invalid mount {}, expected SOURCE[:TARGET][:ro|rw]", mount),
+    }
+}
+
+/// Parses a host directory mount, e.g. `./data:/data:ro`, the guest path
+/// defaults to the host path.
+pub fn parse_dir_mount(mount: &str) -> Result<DirMount> {
+    let (host_path, guest_path, read_only) = split_mount(mount)?;
+    Ok(DirMount {
+        host_path: absolute(host_path)?.to_string_lossy().to_string(),
+        guest_path: guest_path.unwrap_or(host_path).to_string(),
+        read_only,
+    })
+}
+
+/// Parses a named volume mount, e.g. `data:/data:ro`.
+pub fn parse_volume_mount(mount: &str) -> Result<VolumeMount> {
+    let (name, guest_path, read_only) = split_mount(mount)?;
+    let guest_path = guest_path.ok_or_else(|| anyhow!("invalid volume {}, expected NAME:GUEST[:ro|rw]", mount))?;
+    Ok(VolumeMount {
+        name: name.to_string(),
+        guest_path: guest_path.to_string(),
+        read_only,
+    })
+}
+
+#[derive(Args)]
+pub struct EnvOptions {
+    /// Set an environment variable, KEY alone takes the value from the current environment
+    #[arg(short, long = "env", value_name = "KEY[=VALUE]", value_parser = parse_env)]
+    envs: Vec<(String, String)>,
+    /// Read environment variables from a file of KEY=VALUE lines
+    #[arg(long = "env-file", value_name = "PATH")]
+    env_files: Vec<PathBuf>,
+    /// Inherit the environment of the daemon, explicitly set variables take precedence
+    #[arg(long)]
+    pub inherit_env: bool,
+}
+
+impl EnvOptions {
+    /// Returns the environment variables, those passed by `--env` override
+    /// the ones read from the env files.
+    pub fn env(&self) -> Result<HashMap<String, String>> {
+        let mut env = HashMap::new();
+        for path in &self.env_files {
+            let contents = fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+            for line in contents.lines() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let (key, value) = parse_env(line).with_context(|| format!("invalid line in {}", path.display()))?;
+                env.insert(key, value);
+            }
+        }
+        env.extend(self.envs.iter().cloned());
+        Ok(env)
+    }
+}
+
+/// Parses an environment variable in the form of `KEY=VALUE` or `KEY`.
+fn parse_env(env: &str) -> Result<(String, String)> {
+    let (key, value) = match env.split_once('=') {
+        Some((key, value)) => (key, value.to_string()),
+        None => (
+            env,
+            std::env::var(env).map_err(|_| anyhow!("environment variable {} is not set", env))?,
+        ),
+    };
+    if key.is_empty() {
+        bail!("invalid environment variable {}, expected KEY[=VALUE]", env);
+    }
+    Ok((key.to_string(), value))
+}
+
+/// Parses a label in the form of `KEY=VALUE`.
+pub fn parse_label(label: &str) -> Result<(String, String)> {
+    match label.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => bail!("invalid label {}, expected KEY=VALUE", label),
+    }
+}
+
+/// Parses a route in the form of `host=HOST,path=PATH`, either of which can
+/// be omitted, the path defaults to `/`.
+pub fn parse_route(route: &str) -> Result<Route> {
+    let mut parsed = Route {
+        host: String::new(),
+        path: "/".to_string(),
+    };
+    for part in route.split(',') {
+        match part.split_once('=') {
+            Some(("host", host)) => parsed.host = host.to_string(),
+            Some(("path", path)) => parsed.path = path.to_string(),
+            _ => bail!("invalid route {}, expected host=HOST,path=PATH", route),
+        }
+    }
+    Ok(parsed)
+}
+
+/// The key sequence for detaching from a program.
+#[derive(Clone)]
+pub struct DetachKeys(pub Vec<u8>);
+
+/// Parses a detach key sequence, e.g. `ctrl-p,ctrl-q`, each key is either a
+/// single character or `ctrl-` followed by one of `a-z@[\]^_`.
+pub fn parse_detach_keys(keys: &str) -> Result<DetachKeys> {
+    let keys = keys
+        .split(',')
+        .map(|key| {
+            let control = key.strip_prefix("ctrl-");
+            let byte = match control.unwrap_or(key).as_bytes() {
+                [c] if control.is_none() => *c,
+                [c @ b'a'..=b'z'] => c - b'a' + 1,
+                [c @ (b'@' | b'[' | b'\\' | b']' | b'^' | b'_')] => c - b'@',
+                _ => bail!("invalid detach key {:?}", key),
+            };
+            Ok(byte)
+        })
+        .collect::<Result<_>>()?;
+    Ok(DetachKeys(keys))
+}
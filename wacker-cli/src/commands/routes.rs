@@ -0,0 +1,66 @@
+use crate::commands::STATUS;
+use anyhow::{anyhow, Result};
+use clap::{Parser, Subcommand};
+use tabled::{
+    settings::{Padding, Style},
+    Table, Tabled,
+};
+use tonic::transport::Channel;
+use wacker::Client;
+
+#[derive(Subcommand)]
+pub enum RoutesCommand {
+    /// Lists the routes of the ingress listener
+    #[command(visible_alias = "list")]
+    Ls(ListRoutesCommand),
+}
+
+#[derive(Parser)]
+pub struct ListRoutesCommand {}
+
+#[derive(Tabled)]
+struct Route {
+    #[tabled(rename = "HOST")]
+    host: String,
+    #[tabled(rename = "PATH")]
+    path: String,
+    #[tabled(rename = "PROGRAM")]
+    program: String,
+    #[tabled(rename = "STATUS")]
+    status: &'static str,
+}
+
+impl RoutesCommand {
+    /// Executes the command.
+    pub async fn execute(self, mut client: Client<Channel>) -> Result<()> {
+        match self {
+            Self::Ls(_) => {
+                let response = client
+                    .list_routes(())
+                    .await
+                    .map_err(|err| anyhow!(err.message().to_string()))?
+                    .into_inner();
+
+                let routes: Vec<Route> = response
+                    .routes
+                    .into_iter()
+                    .map(|route| Route {
+                        host: match route.host.is_empty() {
+                            true => "*".to_string(),
+                            false => route.host,
+                        },
+                        path: route.path,
+                        program: route.id,
+                        status: STATUS.get(&route.status).unwrap_or(&"Unknown"),
+                    })
+                    .collect();
+
+                let mut table = Table::new(routes);
+                table.with(Padding::new(0, 2, 0, 0)).with(Style::blank());
+
+                println!("{table}");
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,13 +1,54 @@
+use crate::commands::{
+    attach, parse_detach_keys, parse_dir_mount, parse_label, parse_restart_policy, parse_volume_mount, DetachKeys,
+    EnvOptions, LimitOptions, LogOptions, TerminalMode, DEFAULT_DETACH_KEYS,
+};
 use anyhow::{anyhow, Result};
 use clap::Parser;
+use std::process;
 use tonic::transport::Channel;
-use wacker::{Client, RunRequest};
+use wacker::{attach_request, AttachRequest, Client, DirMount, RestartPolicy, RunRequest, VolumeMount};
 
 #[derive(Parser)]
 pub struct RunCommand {
     /// Program file path/URL
     #[arg(required = true)]
     path: String,
+    #[command(flatten)]
+    limits: LimitOptions,
+    /// Stop the program if it is still running after this many seconds
+    #[arg(long, value_name = "SECONDS")]
+    timeout: Option<u64>,
+    /// Preopen a host directory for the program, can be specified multiple times
+    #[arg(long = "dir", value_name = "HOST[:GUEST][:ro]", value_parser = parse_dir_mount)]
+    dirs: Vec<DirMount>,
+    /// Mount a named volume, can be specified multiple times
+    #[arg(short, long = "volume", value_name = "NAME:GUEST[:ro]", value_parser = parse_volume_mount)]
+    volumes: Vec<VolumeMount>,
+    #[command(flatten)]
+    env: EnvOptions,
+    #[command(flatten)]
+    log: LogOptions,
+    /// Set a label on the program, can be specified multiple times
+    #[arg(short, long = "label", value_name = "KEY=VALUE", value_parser = parse_label)]
+    labels: Vec<(String, String)>,
+    /// Restart policy of the program: no, on-failure[:max-retries], always or unless-stopped
+    #[arg(long, value_name = "POLICY", value_parser = parse_restart_policy)]
+    restart: Option<RestartPolicy>,
+    /// Run the program in the foreground, stream its output and exit with its exit code
+    #[arg(short, long)]
+    attach: bool,
+    /// Keep the stdin of the program open and forward stdin to it, implies --attach
+    #[arg(short, long)]
+    interactive: bool,
+    /// Make the program see a terminal, implies --attach
+    #[arg(short, long)]
+    tty: bool,
+    /// Run the program in the background even with --interactive or --tty, use `wacker attach` to attach to it
+    #[arg(short, long, conflicts_with = "attach")]
+    detach: bool,
+    /// Key sequence for detaching from the program
+    #[arg(long, value_name = "KEYS", default_value = DEFAULT_DETACH_KEYS, value_parser = parse_detach_keys)]
+    detach_keys: DetachKeys,
     /// Arguments to pass to the WebAssembly module.
     #[arg(trailing_var_arg = true)]
     args: Vec<String>,
@@ -16,13 +57,36 @@ pub struct RunCommand {
 impl RunCommand {
     /// Executes the command.
     pub async fn execute(self, mut client: Client<Channel>) -> Result<()> {
-        match client
-            .run(RunRequest {
-                path: self.path.to_string(),
-                args: self.args,
-            })
-            .await
-        {
+        let foreground = (self.attach || self.interactive || self.tty) && !self.detach;
+        let request = RunRequest {
+            path: self.path.to_string(),
+            args: self.args,
+            limits: Some(self.limits.into()),
+            timeout: self.timeout.unwrap_or_default(),
+            dirs: self.dirs,
+            env: self.env.env()?,
+            inherit_env: self.env.inherit_env,
+            volumes: self.volumes,
+            restart: self.restart,
+            log: Some(self.log.into()),
+            labels: self.labels.into_iter().collect(),
+            interactive: self.interactive,
+            tty: self.tty,
+        };
+
+        if foreground {
+            let request = AttachRequest {
+                request: Some(attach_request::Request::Run(request)),
+            };
+            let terminal = TerminalMode::enable(self.interactive && self.tty);
+            let exit_code = attach(client, request, self.interactive, self.detach_keys).await;
+            drop(terminal);
+            return match exit_code? {
+                Some(code) => process::exit(code),
+                None => Ok(()),
+            };
+        }
+        match client.run(request).await {
             Ok(_) => Ok(()),
             Err(err) => Err(anyhow!(err.message().to_string())),
         }
@@ -1,8 +1,13 @@
+use crate::commands::{
+    parse_dir_mount, parse_label, parse_restart_policy, parse_route, parse_volume_mount, EnvOptions, LimitOptions,
+    LogOptions,
+};
 use anyhow::{anyhow, Result};
 use clap::Parser;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{absolute, PathBuf};
 use tonic::transport::Channel;
-use wacker::{Client, ServeRequest};
+use wacker::{Client, DirMount, RateLimit, RestartPolicy, Route, ServeRequest, VolumeMount};
 
 const DEFAULT_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 8080);
 
@@ -11,9 +16,71 @@ pub struct ServeCommand {
     /// Program file path/URL
     #[arg(required = true)]
     path: String,
-    /// Socket address for the web server to bind to
-    #[arg(long = "addr", default_value_t = DEFAULT_ADDR )]
-    addr: SocketAddr,
+    /// Socket address for the web server to bind to [default: 0.0.0.0:8080 unless there are routes]
+    #[arg(long = "addr")]
+    addr: Option<SocketAddr>,
+    /// Serve on the ingress listener of the daemon for the requests of a host and path prefix, can be specified multiple times
+    #[arg(long = "route", value_name = "host=HOST,path=PATH", value_parser = parse_route)]
+    routes: Vec<Route>,
+    #[command(flatten)]
+    limits: LimitOptions,
+    /// Fuel budget of each request
+    #[arg(long, value_name = "N")]
+    request_fuel: Option<u64>,
+    /// Respond with 504 if a request is not answered after this many seconds
+    #[arg(long, value_name = "SECONDS")]
+    request_timeout: Option<u64>,
+    /// Maximum number of open connections, further connections wait until one is closed
+    #[arg(long, value_name = "N")]
+    max_connections: Option<u32>,
+    /// Maximum number of requests handled at the same time
+    #[arg(long, value_name = "N")]
+    max_concurrent_requests: Option<u32>,
+    /// Number of requests waiting for --max-concurrent-requests, requests beyond are answered with 503
+    #[arg(long, value_name = "N", requires = "max_concurrent_requests")]
+    request_backlog: Option<u32>,
+    /// Number of requests per second each client can make, requests beyond are answered with 429
+    #[arg(long, value_name = "N")]
+    rate_limit: Option<u32>,
+    /// Number of requests each client can make at once, defaults to --rate-limit
+    #[arg(long, value_name = "N", requires = "rate_limit")]
+    rate_limit_burst: Option<u32>,
+    /// Identify the clients of --rate-limit by this request header instead of their IP address, a new value also counts against the IP address
+    #[arg(long, value_name = "NAME", requires = "rate_limit")]
+    rate_limit_header: Option<String>,
+    /// Only accept HTTP/2 connections, including HTTP/2 over cleartext with prior knowledge
+    #[arg(long)]
+    http2_only: bool,
+    /// Serve over HTTPS with the certificate chain in this PEM file, which is reloaded once modified
+    #[arg(long, value_name = "PATH", requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+    /// PEM file of the private key of --tls-cert
+    #[arg(long, value_name = "PATH", requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+    /// Preopen a host directory for the program, can be specified multiple times
+    #[arg(long = "dir", value_name = "HOST[:GUEST][:ro]", value_parser = parse_dir_mount)]
+    dirs: Vec<DirMount>,
+    /// Mount a named volume, can be specified multiple times
+    #[arg(short, long = "volume", value_name = "NAME:GUEST[:ro]", value_parser = parse_volume_mount)]
+    volumes: Vec<VolumeMount>,
+    #[command(flatten)]
+    env: EnvOptions,
+    #[command(flatten)]
+    log: LogOptions,
+    /// Set a label on the program, can be specified multiple times
+    #[arg(short, long = "label", value_name = "KEY=VALUE", value_parser = parse_label)]
+    labels: Vec<(String, String)>,
+    /// Restart policy of the program: no, on-failure[:max-retries], always or unless-stopped
+    #[arg(long, value_name = "POLICY", value_parser = parse_restart_policy)]
+    restart: Option<RestartPolicy>,
+}
+
+/// Makes the path absolute, since the daemon doesn't share the working directory.
+fn absolute_path(path: Option<PathBuf>) -> Result<String> {
+    match path {
+        Some(path) => Ok(absolute(path)?.to_string_lossy().to_string()),
+        None => Ok(String::new()),
+    }
 }
 
 impl ServeCommand {
@@ -22,7 +89,33 @@ impl ServeCommand {
         match client
             .serve(ServeRequest {
                 path: self.path.to_string(),
-                addr: self.addr.to_string(),
+                addr: match (self.addr, self.routes.is_empty()) {
+                    (Some(addr), _) => addr.to_string(),
+                    (None, true) => DEFAULT_ADDR.to_string(),
+                    (None, false) => String::new(),
+                },
+                routes: self.routes,
+                limits: Some(self.limits.into()),
+                request_fuel: self.request_fuel.unwrap_or_default(),
+                request_timeout: self.request_timeout.unwrap_or_default(),
+                dirs: self.dirs,
+                env: self.env.env()?,
+                inherit_env: self.env.inherit_env,
+                volumes: self.volumes,
+                restart: self.restart,
+                log: Some(self.log.into()),
+                labels: self.labels.into_iter().collect(),
+                http2_only: self.http2_only,
+                tls_cert: absolute_path(self.tls_cert)?,
+                tls_key: absolute_path(self.tls_key)?,
+                max_connections: self.max_connections.unwrap_or_default(),
+                max_concurrent_requests: self.max_concurrent_requests.unwrap_or_default(),
+                request_backlog: self.request_backlog.unwrap_or_default(),
+                rate_limit: self.rate_limit.map(|rate| RateLimit {
+                    rate,
+                    burst: self.rate_limit_burst.unwrap_or_default(),
+                    header: self.rate_limit_header.unwrap_or_default(),
+                }),
             })
             .await
         {
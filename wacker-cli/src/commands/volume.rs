@@ -0,0 +1,122 @@
+use anyhow::{anyhow, Result};
+use clap::{Parser, Subcommand};
+use serde_json::json;
+use tabled::{
+    settings::{Padding, Style},
+    Table, Tabled,
+};
+use tonic::transport::Channel;
+use wacker::{
+    utils::{format_rfc3339, format_time},
+    Client, CreateVolumeRequest, InspectVolumeRequest, RemoveVolumeRequest,
+};
+
+#[derive(Subcommand)]
+pub enum VolumeCommand {
+    /// Creates a volume
+    Create(CreateVolumeCommand),
+    /// Lists volumes
+    #[command(visible_alias = "list")]
+    Ls(ListVolumesCommand),
+    /// Removes volumes, which must not be used by any program
+    #[command(visible_alias = "remove")]
+    Rm(RemoveVolumeCommand),
+    /// Displays detailed information of volumes
+    Inspect(InspectVolumeCommand),
+}
+
+#[derive(Parser)]
+pub struct CreateVolumeCommand {
+    /// Volume name
+    #[arg(required = true)]
+    name: String,
+}
+
+#[derive(Parser)]
+pub struct ListVolumesCommand {}
+
+#[derive(Parser)]
+pub struct RemoveVolumeCommand {
+    /// Volume names
+    #[arg(required = true, value_name = "NAMEs")]
+    names: Vec<String>,
+}
+
+#[derive(Parser)]
+pub struct InspectVolumeCommand {
+    /// Volume names
+    #[arg(required = true, value_name = "NAMEs")]
+    names: Vec<String>,
+}
+
+#[derive(Tabled)]
+struct Volume {
+    #[tabled(rename = "NAME")]
+    name: String,
+    #[tabled(rename = "CREATED")]
+    created: String,
+    #[tabled(rename = "PROGRAMS")]
+    programs: String,
+}
+
+impl VolumeCommand {
+    /// Executes the command.
+    pub async fn execute(self, mut client: Client<Channel>) -> Result<()> {
+        match self {
+            Self::Create(c) => {
+                let volume = client
+                    .create_volume(CreateVolumeRequest { name: c.name })
+                    .await
+                    .map_err(|err| anyhow!(err.message().to_string()))?
+                    .into_inner();
+                println!("{}", volume.name);
+            }
+            Self::Ls(_) => {
+                let response = client
+                    .list_volumes(())
+                    .await
+                    .map_err(|err| anyhow!(err.message().to_string()))?
+                    .into_inner();
+
+                let volumes: Vec<Volume> = response
+                    .volumes
+                    .into_iter()
+                    .map(|volume| Volume {
+                        name: volume.name,
+                        created: format_time(volume.created_at),
+                        programs: volume.programs.join(", "),
+                    })
+                    .collect();
+
+                let mut table = Table::new(volumes);
+                table.with(Padding::new(0, 2, 0, 0)).with(Style::blank());
+
+                println!("{table}");
+            }
+            Self::Rm(c) => {
+                client
+                    .remove_volume(RemoveVolumeRequest { names: c.names })
+                    .await
+                    .map_err(|err| anyhow!(err.message().to_string()))?;
+            }
+            Self::Inspect(c) => {
+                let mut volumes = vec![];
+                for name in c.names {
+                    let volume = client
+                        .inspect_volume(InspectVolumeRequest { name })
+                        .await
+                        .map_err(|err| anyhow!(err.message().to_string()))?
+                        .into_inner();
+                    volumes.push(json!({
+                        "name": volume.name,
+                        "path": volume.path,
+                        "created_at": format_rfc3339(volume.created_at),
+                        "programs": volume.programs,
+                    }));
+                }
+                println!("{}", serde_json::to_string_pretty(&volumes)?);
+            }
+        }
+        Ok(())
+    }
+}
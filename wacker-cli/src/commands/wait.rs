@@ -0,0 +1,27 @@
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use std::process;
+use tonic::transport::Channel;
+use wacker::{Client, WaitRequest};
+
+#[derive(Parser)]
+pub struct WaitCommand {
+    /// Program ID
+    #[arg(required = true)]
+    id: String,
+}
+
+impl WaitCommand {
+    /// Executes the command.
+    pub async fn execute(self, mut client: Client<Channel>) -> Result<()> {
+        let response = match client.wait(WaitRequest { id: self.id }).await {
+            Ok(resp) => resp.into_inner(),
+            Err(err) => return Err(anyhow!(err.message().to_string())),
+        };
+
+        // Programs that were stopped or trapped have no exit code.
+        let code = response.exit_code.unwrap_or(1);
+        println!("{}", code);
+        process::exit(code);
+    }
+}
@@ -36,6 +36,18 @@ enum Subcommand {
     /// Fetches logs of a program
     #[command(visible_alias = "log")]
     Logs(commands::LogsCommand),
+    /// Displays detailed information of programs
+    Inspect(commands::InspectCommand),
+    /// Waits for a program to exit, and exits with its exit code
+    Wait(commands::WaitCommand),
+    /// Attaches to a running program
+    Attach(commands::AttachCommand),
+    /// Manages volumes
+    #[command(subcommand)]
+    Volume(commands::VolumeCommand),
+    /// Shows the routes of the ingress listener
+    #[command(subcommand)]
+    Routes(commands::RoutesCommand),
 }
 
 impl Wacker {
@@ -51,6 +63,11 @@ impl Wacker {
             Subcommand::Restart(c) => c.execute(client).await,
             Subcommand::Delete(c) => c.execute(client).await,
             Subcommand::Logs(c) => c.execute(client).await,
+            Subcommand::Inspect(c) => c.execute(client).await,
+            Subcommand::Wait(c) => c.execute(client).await,
+            Subcommand::Attach(c) => c.execute(client).await,
+            Subcommand::Volume(c) => c.execute(client).await,
+            Subcommand::Routes(c) => c.execute(client).await,
         }
     }
 }
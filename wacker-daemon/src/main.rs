@@ -1,12 +1,32 @@
 use anyhow::Result;
 use clap::Parser;
+use std::net::SocketAddr;
 use tokio::signal;
-use wacker::Server;
+use wacker::{
+    utils::{parse_log_driver, parse_size},
+    Server,
+};
 
 #[derive(Parser)]
 #[command(name = "wackerd")]
 #[command(author, version = version(), about, long_about = None)]
-struct WackerDaemon {}
+struct WackerDaemon {
+    /// Default size at which the log file of a program is rotated, e.g. 10M
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    log_max_size: Option<u64>,
+    /// Default maximum number of log files kept for each program, including the current one
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    log_max_files: Option<u32>,
+    /// Default log driver of programs: file, json-lines or syslog
+    #[arg(long, value_name = "DRIVER", value_parser = parse_log_driver)]
+    log_driver: Option<u32>,
+    /// Default path of the unix datagram socket of the syslog log driver [default: /dev/log]
+    #[arg(long, value_name = "PATH")]
+    syslog_address: Option<String>,
+    /// Socket address of the ingress listener, which dispatches requests to HTTP programs by their routes
+    #[arg(long, value_name = "ADDR")]
+    ingress_addr: Option<SocketAddr>,
+}
 
 fn version() -> &'static str {
     // If WACKER_VERSION_INFO is set, use it, otherwise use CARGO_PKG_VERSION.
@@ -15,7 +35,23 @@ fn version() -> &'static str {
 
 impl WackerDaemon {
     async fn execute(self) -> Result<()> {
-        Server::new()
+        let mut server = Server::new();
+        if let Some(max_size) = self.log_max_size {
+            server.log_max_size(max_size);
+        }
+        if let Some(max_files) = self.log_max_files {
+            server.log_max_files(max_files);
+        }
+        if let Some(driver) = self.log_driver {
+            server.log_driver(driver);
+        }
+        if let Some(address) = self.syslog_address {
+            server.syslog_address(address);
+        }
+        if let Some(addr) = self.ingress_addr {
+            server.ingress_addr(addr.to_string());
+        }
+        server
             .start(async {
                 signal::ctrl_c().await.expect("failed to listen for event");
             })
@@ -1,6 +1,8 @@
 mod runtime;
 mod server;
 pub mod utils;
+// The generated oneofs hold whole messages, e.g. the run request of attach.
+#[allow(clippy::large_enum_variant)]
 mod proto {
     tonic::include_proto!("wacker");
 }
@@ -14,7 +16,8 @@ use std::fs::{create_dir_all, remove_file};
 use std::future::Future;
 use std::io::{ErrorKind, Write};
 use std::path::{Path, PathBuf};
-use tokio::net::{UnixListener, UnixStream};
+use std::sync::Arc;
+use tokio::net::{TcpListener, UnixListener, UnixStream};
 use tokio_stream::wrappers::UnixListenerStream;
 use tonic::{
     codec::CompressionEncoding,
@@ -23,18 +26,36 @@ use tonic::{
 use tower::service_fn;
 
 pub use self::proto::{
-    wacker_client::WackerClient as Client, DeleteRequest, ListResponse, LogRequest, LogResponse, Program,
-    ProgramResponse, RestartRequest, RunRequest, ServeRequest, StopRequest,
+    attach_request, attach_response, wacker_client::WackerClient as Client, AttachProgram, AttachRequest,
+    AttachResponse, CreateVolumeRequest, DeleteRequest, DirMount, IngressRoute, InspectRequest, InspectResponse,
+    InspectVolumeRequest, ListResponse, ListRoutesResponse, ListVolumesResponse, LogConfig, LogRequest, LogResponse,
+    Program, ProgramDetails, ProgramResponse, RateLimit, RemoveVolumeRequest, ResourceLimits, RestartPolicy,
+    RestartRequest, Route, RunRequest, ServeRequest, StopRequest, Volume, VolumeMount, WaitRequest, WaitResponse,
 };
 
 pub const PROGRAM_STATUS_RUNNING: u32 = 0;
 pub const PROGRAM_STATUS_FINISHED: u32 = 1;
 pub const PROGRAM_STATUS_ERROR: u32 = 2;
 pub const PROGRAM_STATUS_STOPPED: u32 = 3;
+pub const PROGRAM_STATUS_FUEL_EXHAUSTED: u32 = 4;
+pub const PROGRAM_STATUS_TIMED_OUT: u32 = 5;
+pub const PROGRAM_STATUS_RESTARTING: u32 = 6;
+
+pub const LOG_STREAM_STDOUT: u32 = 1;
+pub const LOG_STREAM_STDERR: u32 = 2;
+
+pub const LOG_DRIVER_FILE: u32 = 1;
+pub const LOG_DRIVER_JSON_LINES: u32 = 2;
+pub const LOG_DRIVER_SYSLOG: u32 = 3;
 
 pub const PROGRAM_TYPE_CLI: u32 = 0;
 pub const PROGRAM_TYPE_HTTP: u32 = 1;
 
+pub const RESTART_POLICY_NO: u32 = 0;
+pub const RESTART_POLICY_ON_FAILURE: u32 = 1;
+pub const RESTART_POLICY_ALWAYS: u32 = 2;
+pub const RESTART_POLICY_UNLESS_STOPPED: u32 = 3;
+
 fn get_main_dir() -> Result<PathBuf> {
     match dirs::home_dir() {
         Some(home_dir) => Ok(home_dir.join(".wacker")),
@@ -46,6 +67,8 @@ fn get_main_dir() -> Result<PathBuf> {
 pub struct Server {
     main_dir: Option<PathBuf>,
     is_test: bool,
+    log_options: runtime::LogOptions,
+    ingress_addr: Option<String>,
 }
 
 impl Server {
@@ -63,6 +86,40 @@ impl Server {
         self
     }
 
+    /// Sets the default size in bytes at which the log files of programs are
+    /// rotated.
+    pub fn log_max_size(&mut self, max_size: u64) -> &mut Self {
+        self.log_options.rotation.max_size = Some(max_size);
+        self
+    }
+
+    /// Sets the default maximum number of log files kept for each program.
+    pub fn log_max_files(&mut self, max_files: u32) -> &mut Self {
+        self.log_options.rotation.max_files = Some(max_files);
+        self
+    }
+
+    /// Sets the default log driver of programs, one of the `LOG_DRIVER_*`
+    /// constants.
+    pub fn log_driver(&mut self, driver: u32) -> &mut Self {
+        self.log_options.driver = Some(driver);
+        self
+    }
+
+    /// Sets the default path of the unix datagram socket of the syslog log
+    /// driver.
+    pub fn syslog_address(&mut self, address: String) -> &mut Self {
+        self.log_options.syslog_address = Some(address);
+        self
+    }
+
+    /// Sets the address of the ingress listener, which dispatches requests to
+    /// HTTP programs by the host and path of their routes.
+    pub fn ingress_addr(&mut self, addr: String) -> &mut Self {
+        self.ingress_addr = Some(addr);
+        self
+    }
+
     pub async fn start<F: Future<Output = ()> + Send + 'static>(&self, shutdown: F) -> Result<()> {
         let main_dir = match &self.main_dir {
             Some(p) => p.clone(),
@@ -79,6 +136,11 @@ impl Server {
             create_dir_all(logs_dir.as_path())?;
         }
 
+        let volumes_dir = main_dir.join("volumes");
+        if !volumes_dir.exists() {
+            create_dir_all(volumes_dir.as_path())?;
+        }
+
         let db_path = main_dir.join("db");
         let db = sled::open(db_path)?;
 
@@ -103,13 +165,26 @@ impl Server {
             log_builder.try_init()?;
         }
 
+        let (ingress, ingress_task) = match &self.ingress_addr {
+            Some(addr) => {
+                let listener = TcpListener::bind(addr).await?;
+                info!("ingress listening on {}", listener.local_addr()?);
+                let ingress = Arc::new(runtime::Ingress::default());
+                let task = tokio::spawn(runtime::serve_ingress(listener, ingress.clone()));
+                (Some(ingress), Some(task))
+            }
+            None => (None, None),
+        };
+
         let uds = UnixListener::bind(sock_path.as_path())?;
         let uds_stream = UnixListenerStream::new(uds);
-        let service = proto::wacker_server::WackerServer::new(server::Server::new(db.clone(), logs_dir).await?)
-            .send_compressed(CompressionEncoding::Zstd)
-            .accept_compressed(CompressionEncoding::Zstd)
-            .send_compressed(CompressionEncoding::Gzip)
-            .accept_compressed(CompressionEncoding::Gzip);
+        let service = proto::wacker_server::WackerServer::new(
+            server::Server::new(db.clone(), logs_dir, volumes_dir, self.log_options.clone(), ingress).await?,
+        )
+        .send_compressed(CompressionEncoding::Zstd)
+        .accept_compressed(CompressionEncoding::Zstd)
+        .send_compressed(CompressionEncoding::Gzip)
+        .accept_compressed(CompressionEncoding::Gzip);
 
         info!("server listening on {:?}", sock_path.as_path());
 
@@ -118,6 +193,9 @@ impl Server {
             .serve_with_incoming_shutdown(uds_stream, async move {
                 shutdown.await;
                 info!("Shutting down the server");
+                if let Some(task) = ingress_task {
+                    task.abort();
+                }
                 if let Err(err) = remove_file(sock_path) {
                     if err.kind() != ErrorKind::NotFound {
                         warn!("failed to remove existing socket file: {}", err);
@@ -1,12 +1,17 @@
 use crate::runtime::{
+    default_config_settings,
+    dir::{preopen_core_dirs, preopen_dirs},
+    envs,
     host::Host,
-    logs::LogStream,
-    read, {Engine, ProgramMeta},
+    limits::Limiter,
+    read_module, set_timeout, spawn_epoch_ticker, timeout_error, {Engine, ProgramIo, ProgramMeta, ProgramUsage},
 };
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
-use std::fs::File;
-use wasi_common::{tokio, I32Exit};
+use std::future::Future;
+use std::sync::{atomic::Ordering, Arc};
+use std::time::Duration;
+use wasi_common::{tokio, I32Exit, WasiCtx};
 use wasmtime::component::{Component, ResourceTable};
 use wasmtime::{Config, Module, Store};
 use wasmtime_wasi::{bindings::Command, WasiCtxBuilder};
@@ -17,6 +22,11 @@ pub struct CliEngine {
     engine: wasmtime::Engine,
 }
 
+struct CoreHost {
+    wasi: WasiCtx,
+    limiter: Limiter,
+}
+
 enum RunTarget {
     Core(Module),
     Component(Component),
@@ -24,13 +34,18 @@ enum RunTarget {
 
 impl CliEngine {
     pub fn new(config: &Config) -> Result<Self> {
-        Ok(Self {
-            engine: wasmtime::Engine::new(config)?,
-        })
+        let engine = wasmtime::Engine::new(config)?;
+        spawn_epoch_ticker(&engine);
+        Ok(Self { engine })
     }
 
-    async fn load_module_contents(&self, engine: &wasmtime::Engine, path: &str) -> Result<RunTarget> {
-        let bytes = read(path).await?;
+    async fn load_module_contents(
+        &self,
+        engine: &wasmtime::Engine,
+        path: &str,
+        usage: &ProgramUsage,
+    ) -> Result<RunTarget> {
+        let bytes = read_module(path, usage).await?;
         let mut builder = wasmtime::CodeBuilder::new(engine);
         match builder.wasm_binary_or_text(&bytes, Some(path.as_ref()))?.hint() {
             Some(wasmtime::CodeHint::Component) => Ok(RunTarget::Component(builder.compile_component()?)),
@@ -39,34 +54,76 @@ impl CliEngine {
     }
 }
 
+/// Sets the fuel budget of the store, and records the consumed fuel whenever
+/// the guest calls into or returns to the host.
+fn set_fuel<T>(store: &mut Store<T>, fuel: u64, usage: Arc<ProgramUsage>) -> Result<()> {
+    store.set_fuel(fuel)?;
+    store.fuel_async_yield_interval(Some(10000))?;
+    store.call_hook(move |ctx, _| {
+        usage.fuel_consumed.store(fuel - ctx.get_fuel()?, Ordering::Relaxed);
+        Ok(())
+    });
+    Ok(())
+}
+
+/// Runs the guest, and stops it if it is still running when the timeout
+/// elapses, e.g. while it is blocked in a host call.
+async fn with_timeout<T>(timeout: Option<Duration>, run: impl Future<Output = Result<T>>) -> Result<T> {
+    match timeout {
+        Some(timeout) => ::tokio::time::timeout(timeout, run)
+            .await
+            .unwrap_or_else(|_| Err(timeout_error(timeout))),
+        None => run.await,
+    }
+}
+
+/// Converts the result of the guest into its exit code, the guest exits
+/// with an `I32Exit` error when it calls `proc_exit` or `exit`, and modules
+/// and components have their own `I32Exit` types.
+fn exit_code(result: Result<()>) -> Result<i32> {
+    match result {
+        Ok(()) => Ok(0),
+        Err(err) => {
+            let code = err
+                .downcast_ref::<I32Exit>()
+                .map(|exit| exit.0)
+                .or_else(|| err.downcast_ref::<wasmtime_wasi::I32Exit>().map(|exit| exit.0));
+            code.ok_or(err)
+        }
+    }
+}
+
 #[async_trait]
 impl Engine for CliEngine {
-    async fn run(&self, meta: ProgramMeta, stdout: File) -> Result<()> {
-        let mut args = meta.args;
+    async fn run(&self, _: &str, meta: ProgramMeta, io: ProgramIo, usage: Arc<ProgramUsage>) -> Result<i32> {
+        let mut args = meta.args.clone();
         args.insert(0, meta.path.clone());
+        let envs = envs(&meta);
+        let fuel = meta.fuel.unwrap_or(u64::MAX);
 
-        match self.load_module_contents(&self.engine, &meta.path).await? {
+        match self.load_module_contents(&self.engine, &meta.path, &usage).await? {
             RunTarget::Core(module) => {
-                let stderr = stdout.try_clone()?;
-
-                let wasi_stdout = cap_std::fs::File::from_std(stdout);
-                let wasi_stdout = tokio::File::from_cap_std(wasi_stdout);
-                let wasi_stderr = cap_std::fs::File::from_std(stderr);
-                let wasi_stderr = tokio::File::from_cap_std(wasi_stderr);
-
                 let wasi = tokio::WasiCtxBuilder::new()
-                    .inherit_stdin()
-                    .stdout(Box::new(wasi_stdout))
-                    .stderr(Box::new(wasi_stderr))
+                    .stdin(Box::new(io.stdin))
+                    .stdout(Box::new(io.stdout))
+                    .stderr(Box::new(io.stderr))
                     .args(args.as_ref())?
-                    .inherit_env()?
+                    .envs(envs.as_ref())?
                     .build();
-                let mut store = Store::new(&self.engine, wasi);
-                store.set_fuel(u64::MAX)?;
-                store.fuel_async_yield_interval(Some(10000))?;
+                preopen_core_dirs(&wasi, &meta.dirs)?;
+                let mut store = Store::new(
+                    &self.engine,
+                    CoreHost {
+                        wasi,
+                        limiter: Limiter::new(&meta),
+                    },
+                );
+                store.limiter(|host| &mut host.limiter);
+                set_fuel(&mut store, fuel, usage)?;
+                set_timeout(&mut store, meta.timeout);
 
                 let mut linker = wasmtime::Linker::new(&self.engine);
-                tokio::add_to_linker(&mut linker, |cx| cx)?;
+                tokio::add_to_linker(&mut linker, |host: &mut CoreHost| &mut host.wasi)?;
 
                 // Instantiate into our own unique store using the shared linker, afterwards
                 // acquiring the `_start` function for the module and executing it.
@@ -76,50 +133,53 @@ impl Engine for CliEngine {
                     .or_else(|| instance.get_func(&mut store, ""));
 
                 match func {
-                    Some(func) => match func.call_async(&mut store, &[], &mut []).await {
-                        Ok(()) => Ok(()),
-                        Err(err) => {
-                            match err.downcast_ref::<I32Exit>() {
-                                // Ignore errors with exit code 0
-                                Some(exit_error) if exit_error.0 == 0 => Ok(()),
-                                _ => Err(err),
-                            }
-                        }
-                    },
+                    Some(func) => {
+                        exit_code(with_timeout(meta.timeout, func.call_async(&mut store, &[], &mut [])).await)
+                    }
                     None => Err(anyhow!("no main function to run")),
                 }
             }
             RunTarget::Component(component) => {
-                let stderr = stdout.try_clone()?;
-
-                let ctx = WasiCtxBuilder::new()
-                    .inherit_stdin()
-                    .stdout(LogStream { output: stdout })
-                    .stderr(LogStream { output: stderr })
+                let mut builder = WasiCtxBuilder::new();
+                builder
+                    .stdin(io.stdin)
+                    .stdout(io.stdout)
+                    .stderr(io.stderr)
                     .args(args.as_ref())
-                    .inherit_env()
-                    .build();
+                    .envs(envs.as_ref());
+                preopen_dirs(&mut builder, &meta.dirs)?;
+                let ctx = builder.build();
                 let mut store = Store::new(
                     &self.engine,
                     Host {
                         table: ResourceTable::new(),
                         ctx,
                         http: WasiHttpCtx::new(),
+                        limiter: Limiter::new(&meta),
                     },
                 );
-                store.set_fuel(u64::MAX)?;
-                store.fuel_async_yield_interval(Some(10000))?;
+                store.limiter(|host| &mut host.limiter);
+                set_fuel(&mut store, fuel, usage)?;
+                set_timeout(&mut store, meta.timeout);
 
                 let mut linker = wasmtime::component::Linker::new(&self.engine);
                 wasmtime_wasi::add_to_linker_async(&mut linker)?;
                 wasmtime_wasi_http::add_only_http_to_linker_async(&mut linker)?;
 
                 let command = Command::instantiate_async(&mut store, &component, &linker).await?;
-                match command.wasi_cli_run().call_run(&mut store).await {
-                    Ok(_) => Ok(()),
-                    Err(e) => Err(anyhow!("call run function error: {}", e)),
+                match with_timeout(meta.timeout, command.wasi_cli_run().call_run(&mut store)).await {
+                    // The run function returns an error without a code when
+                    // the component fails, it's the same as exiting with 1.
+                    Ok(result) => Ok(if result.is_ok() { 0 } else { 1 }),
+                    Err(e) => exit_code(Err(e)).map_err(|e| e.context("call run function error")),
                 }
             }
         }
     }
+
+    fn config(&self) -> Vec<(String, String)> {
+        let mut settings = default_config_settings();
+        settings.push(("allocation_strategy".to_string(), "on_demand".to_string()));
+        settings
+    }
 }
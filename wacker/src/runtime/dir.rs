@@ -0,0 +1,125 @@
+use crate::runtime::PreopenDir;
+use async_trait::async_trait;
+use cap_std::{ambient_authority, fs::Dir};
+use std::any::Any;
+use std::path::PathBuf;
+use wasi_common::{
+    dir::{OpenResult, ReaddirCursor, ReaddirEntity},
+    file::{FdFlags, Filestat, OFlags},
+    tokio, Error, ErrorExt, SystemTimeSpec, WasiCtx, WasiDir,
+};
+use wasmtime_wasi::{DirPerms, FilePerms, WasiCtxBuilder};
+
+/// Preopens the directories for a WASI preview1 module.
+pub fn preopen_core_dirs(ctx: &WasiCtx, dirs: &[PreopenDir]) -> anyhow::Result<()> {
+    for preopen in dirs {
+        let dir = Dir::open_ambient_dir(&preopen.host_path, ambient_authority())?;
+        let mut dir: Box<dyn WasiDir> = Box::new(tokio::Dir::from_cap_std(dir));
+        if preopen.read_only {
+            dir = Box::new(ReadOnlyDir(dir));
+        }
+        ctx.push_preopened_dir(dir, &preopen.guest_path)?;
+    }
+    Ok(())
+}
+
+/// Preopens the directories for a WASI component.
+pub fn preopen_dirs(builder: &mut WasiCtxBuilder, dirs: &[PreopenDir]) -> anyhow::Result<()> {
+    for preopen in dirs {
+        let (dir_perms, file_perms) = match preopen.read_only {
+            true => (DirPerms::READ, FilePerms::READ),
+            false => (DirPerms::all(), FilePerms::all()),
+        };
+        builder.preopened_dir(&preopen.host_path, &preopen.guest_path, dir_perms, file_perms)?;
+    }
+    Ok(())
+}
+
+/// A preopened directory of a WASI preview1 module that can only be read.
+///
+/// Every directory opened through it is read-only as well.
+struct ReadOnlyDir(Box<dyn WasiDir>);
+
+#[async_trait]
+impl WasiDir for ReadOnlyDir {
+    fn as_any(&self) -> &dyn Any {
+        // Don't expose the inner directory, otherwise it could be used as the
+        // target of `rename` or `hard_link` from a writable directory.
+        self
+    }
+
+    async fn open_file(
+        &self,
+        symlink_follow: bool,
+        path: &str,
+        oflags: OFlags,
+        read: bool,
+        write: bool,
+        fdflags: FdFlags,
+    ) -> Result<OpenResult, Error> {
+        if write || oflags.intersects(OFlags::CREATE | OFlags::TRUNCATE) {
+            return Err(Error::perm());
+        }
+        match self
+            .0
+            .open_file(symlink_follow, path, oflags, read, write, fdflags)
+            .await?
+        {
+            OpenResult::Dir(dir) => Ok(OpenResult::Dir(Box::new(ReadOnlyDir(dir)))),
+            file => Ok(file),
+        }
+    }
+
+    async fn create_dir(&self, _path: &str) -> Result<(), Error> {
+        Err(Error::perm())
+    }
+
+    async fn readdir(
+        &self,
+        cursor: ReaddirCursor,
+    ) -> Result<Box<dyn Iterator<Item = Result<ReaddirEntity, Error>> + Send>, Error> {
+        self.0.readdir(cursor).await
+    }
+
+    async fn symlink(&self, _old_path: &str, _new_path: &str) -> Result<(), Error> {
+        Err(Error::perm())
+    }
+
+    async fn remove_dir(&self, _path: &str) -> Result<(), Error> {
+        Err(Error::perm())
+    }
+
+    async fn unlink_file(&self, _path: &str) -> Result<(), Error> {
+        Err(Error::perm())
+    }
+
+    async fn read_link(&self, path: &str) -> Result<PathBuf, Error> {
+        self.0.read_link(path).await
+    }
+
+    async fn get_filestat(&self) -> Result<Filestat, Error> {
+        self.0.get_filestat().await
+    }
+
+    async fn get_path_filestat(&self, path: &str, follow_symlinks: bool) -> Result<Filestat, Error> {
+        self.0.get_path_filestat(path, follow_symlinks).await
+    }
+
+    async fn rename(&self, _path: &str, _dest_dir: &dyn WasiDir, _dest_path: &str) -> Result<(), Error> {
+        Err(Error::perm())
+    }
+
+    async fn hard_link(&self, _path: &str, _target_dir: &dyn WasiDir, _target_path: &str) -> Result<(), Error> {
+        Err(Error::perm())
+    }
+
+    async fn set_times(
+        &self,
+        _path: &str,
+        _atime: Option<SystemTimeSpec>,
+        _mtime: Option<SystemTimeSpec>,
+        _follow_symlinks: bool,
+    ) -> Result<(), Error> {
+        Err(Error::perm())
+    }
+}
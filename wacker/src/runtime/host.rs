@@ -1,3 +1,4 @@
+use crate::runtime::limits::Limiter;
 use wasmtime::component::ResourceTable;
 use wasmtime_wasi::{WasiCtx, WasiView};
 use wasmtime_wasi_http::{WasiHttpCtx, WasiHttpView};
@@ -6,6 +7,7 @@ pub struct Host {
     pub table: ResourceTable,
     pub ctx: WasiCtx,
     pub http: WasiHttpCtx,
+    pub limiter: Limiter,
 }
 
 impl WasiView for Host {
@@ -1,20 +1,34 @@
 use crate::runtime::{
+    default_config_settings,
+    dir::preopen_dirs,
+    envs,
     host::Host,
-    logs::LogStream,
-    read, {Engine, ProgramMeta},
+    ingress::Ingress,
+    limits::Limiter,
+    rate_limit::RateLimiter,
+    read_module, set_timeout, spawn_epoch_ticker,
+    tls::tls_acceptor,
+    {Engine, ProgramIo, ProgramMeta, ProgramUsage},
 };
-use anyhow::{anyhow, bail, Result};
+use anyhow::{anyhow, bail, Error, Result};
 use async_trait::async_trait;
-use hyper::Request;
-use std::fs::File;
+use http_body_util::{BodyExt, Empty};
+use hyper::{
+    header::{HeaderValue, RETRY_AFTER},
+    Request, StatusCode,
+};
+use hyper_util::{rt::TokioExecutor, server::conn::auto};
 use std::io::Write;
+use std::net::IpAddr;
 use std::sync::{
-    atomic::{AtomicU64, Ordering},
+    atomic::{AtomicU64, AtomicUsize, Ordering},
     Arc,
 };
+use std::time::Duration;
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
 use wasmtime::{
     component::{Component, Linker, ResourceTable},
-    Config, InstanceAllocationStrategy, Memory, MemoryType, PoolingAllocationConfig, Store,
+    Config, InstanceAllocationStrategy, Memory, MemoryType, PoolingAllocationConfig, Store, Trap,
 };
 use wasmtime_wasi::WasiCtxBuilder;
 use wasmtime_wasi_http::{
@@ -24,147 +38,405 @@ use wasmtime_wasi_http::{
     WasiHttpCtx, WasiHttpView,
 };
 
+/// How long a client may take to complete the TLS handshake, so that stalled
+/// clients don't hold on to the connection permits.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
 #[derive(Clone)]
 pub struct HttpEngine {
     engine: wasmtime::Engine,
+    pooling_allocator: bool,
+    ingress: Option<Arc<Ingress>>,
 }
 
 impl HttpEngine {
-    pub fn new(config: &Config) -> Result<Self> {
+    pub fn new(config: &Config, ingress: Option<Arc<Ingress>>) -> Result<Self> {
         let mut config = config.clone();
-        if use_pooling_allocator_by_default().unwrap_or(false) {
+        let pooling_allocator = use_pooling_allocator_by_default().unwrap_or(false);
+        if pooling_allocator {
             let pooling_config = PoolingAllocationConfig::default();
             config.allocation_strategy(InstanceAllocationStrategy::Pooling(pooling_config));
         }
 
+        let engine = wasmtime::Engine::new(&config)?;
+        spawn_epoch_ticker(&engine);
         Ok(Self {
-            engine: wasmtime::Engine::new(&config)?,
+            engine,
+            pooling_allocator,
+            ingress,
         })
     }
-
-    fn new_store(&self, req_id: u64, stdout: File) -> Result<Store<Host>> {
-        let mut builder = WasiCtxBuilder::new();
-
-        let stderr = stdout.try_clone()?;
-        builder.stdout(LogStream { output: stdout });
-        builder.stderr(LogStream { output: stderr });
-
-        builder.env("REQUEST_ID", req_id.to_string());
-
-        let host = Host {
-            table: ResourceTable::new(),
-            ctx: builder.build(),
-            http: WasiHttpCtx::new(),
-        };
-
-        let mut store = Store::new(&self.engine, host);
-        store.set_fuel(u64::MAX)?;
-
-        Ok(store)
-    }
 }
 
 #[async_trait]
 impl Engine for HttpEngine {
-    async fn run(&self, meta: ProgramMeta, stdout: File) -> Result<()> {
-        use hyper::server::conn::http1;
-
+    async fn run(&self, id: &str, meta: ProgramMeta, io: ProgramIo, usage: Arc<ProgramUsage>) -> Result<i32> {
         let mut linker = Linker::new(&self.engine);
         wasmtime_wasi::add_to_linker_async(&mut linker)?;
         wasmtime_wasi_http::add_only_http_to_linker_async(&mut linker)?;
 
-        let bytes = read(&meta.path).await?;
+        let bytes = read_module(&meta.path, &usage).await?;
         let component = Component::from_binary(&self.engine, &bytes)?;
         let instance = linker.instantiate_pre(&component)?;
         let instance = ProxyPre::new(instance)?;
 
-        let listener = tokio::net::TcpListener::bind(meta.addr.unwrap()).await?;
+        let listener = match &meta.addr {
+            Some(addr) => Some(tokio::net::TcpListener::bind(addr).await?),
+            None => None,
+        };
 
-        let mut stdout = stdout.try_clone()?;
-        stdout.write_fmt(format_args!("Serving HTTP on http://{}/\n", listener.local_addr()?))?;
+        let acceptor = tls_acceptor(&meta, io.stderr.clone())?;
+        if let Some(listener) = &listener {
+            let scheme = if acceptor.is_some() { "https" } else { "http" };
+            io.stdout.clone().write_fmt(format_args!(
+                "Serving HTTP on {}://{}/\n",
+                scheme,
+                listener.local_addr()?
+            ))?;
+        }
+
+        // Accepts both HTTP/1.1 and HTTP/2, which is detected by the preface of
+        // the connection, so HTTP/2 works over cleartext with prior knowledge.
+        let mut builder = auto::Builder::new(TokioExecutor::new());
+        if meta.http2_only {
+            builder = builder.http2_only();
+        }
+        let builder = Arc::new(builder);
+        let connections = meta
+            .max_connections
+            .map(|max_connections| Arc::new(Semaphore::new(max_connections as usize)));
+        let routes = meta.routes.clone();
+        let handler = ProxyHandler::new(self.clone(), instance, meta, usage, io.clone())?;
 
-        let handler = ProxyHandler::new(self.clone(), instance, stdout.try_clone()?);
+        // The routes are dispatched to the program until it exits.
+        let _registration = match (&self.ingress, routes.is_empty()) {
+            (_, true) => None,
+            (Some(ingress), false) => Some(ingress.register(id, &routes, &handler)?),
+            (None, false) => bail!("the daemon has no ingress listener for the routes of the program"),
+        };
+        for route in &routes {
+            io.stdout
+                .clone()
+                .write_fmt(format_args!("Serving HTTP on the ingress route {}\n", route))?;
+        }
 
+        let Some(listener) = listener else {
+            handler.0.fuel_exhausted.notified().await;
+            return Err(Error::from(Trap::OutOfFuel).context("the fuel budget of the program is exhausted"));
+        };
+        let tls = acceptor.is_some();
         loop {
-            let (stream, _) = listener.accept().await?;
-            let stream = TokioIo::new(stream);
+            // Once the program has as many connections as allowed, further
+            // connections wait in the listen backlog until one is closed.
+            let (stream, addr, permit) = tokio::select! {
+                res = async {
+                    let permit = match &connections {
+                        Some(connections) => Some(connections.clone().acquire_owned().await?),
+                        None => None,
+                    };
+                    let (stream, addr) = listener.accept().await?;
+                    Ok::<_, Error>((stream, addr, permit))
+                } => res?,
+                _ = handler.0.fuel_exhausted.notified() => {
+                    return Err(Error::from(Trap::OutOfFuel).context("the fuel budget of the program is exhausted"));
+                }
+            };
             let h = handler.clone();
-            let mut stdout = stdout.try_clone()?;
+            let builder = builder.clone();
+            let acceptor = acceptor.clone();
+            let mut stderr = io.stderr.clone();
             tokio::task::spawn(async move {
-                if let Err(e) = http1::Builder::new()
-                    .keep_alive(true)
-                    .serve_connection(
-                        stream,
-                        hyper::service::service_fn(move |req| handle_request(h.clone(), req)),
-                    )
-                    .await
-                {
-                    let _ = stdout.write_fmt(format_args!("serve error: {e:?}\n"));
+                let res = match acceptor {
+                    Some(acceptor) => {
+                        match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                            Ok(Ok(stream)) => {
+                                let _permit = permit;
+                                serve_connection(&builder, stream, addr.ip(), tls, h).await
+                            }
+                            Ok(Err(e)) => {
+                                drop(permit);
+                                Err(anyhow!("TLS handshake failed: {e}"))
+                            }
+                            Err(_) => {
+                                drop(permit);
+                                Err(anyhow!(
+                                    "TLS handshake timed out after {}s",
+                                    TLS_HANDSHAKE_TIMEOUT.as_secs()
+                                ))
+                            }
+                        }
+                    }
+                    None => {
+                        let _permit = permit;
+                        serve_connection(&builder, stream, addr.ip(), tls, h).await
+                    }
+                };
+                if let Err(e) = res {
+                    let _ = stderr.write_fmt(format_args!("serve error: {e:?}\n"));
                 }
             });
         }
     }
+
+    fn config(&self) -> Vec<(String, String)> {
+        let allocation_strategy = match self.pooling_allocator {
+            true => "pooling",
+            false => "on_demand",
+        };
+        let mut settings = default_config_settings();
+        settings.push(("allocation_strategy".to_string(), allocation_strategy.to_string()));
+        settings
+    }
+}
+
+async fn serve_connection<S>(
+    builder: &auto::Builder<TokioExecutor>,
+    stream: S,
+    ip: IpAddr,
+    tls: bool,
+    handler: ProxyHandler,
+) -> Result<()>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+{
+    builder
+        .serve_connection(
+            TokioIo::new(stream),
+            hyper::service::service_fn(move |req| serve_request(handler.clone(), ip, tls, req)),
+        )
+        .await
+        .map_err(|e| anyhow!(e))
+}
+
+/// Handles a request of the client at `ip`, which is received over TLS if
+/// `tls` is true.
+pub(crate) async fn serve_request(
+    handler: ProxyHandler,
+    ip: IpAddr,
+    tls: bool,
+    req: Request<hyper::body::Incoming>,
+) -> Result<hyper::Response<HyperOutgoingBody>> {
+    // Throttled requests are rejected before the guest is instantiated.
+    if let Some(rate_limiter) = &handler.0.rate_limiter {
+        if let Err(wait) = rate_limiter.check(ip, &req) {
+            handler.0.io.stderr.clone().write_fmt(format_args!(
+                "Request from {ip} rate limited: {} to {}\n",
+                req.method(),
+                req.uri()
+            ))?;
+            return Ok(too_many_requests(wait));
+        }
+    }
+    handle_request(handler, tls, req).await
 }
 
 struct ProxyHandlerInner {
     http_engine: HttpEngine,
     instance_pre: ProxyPre<Host>,
+    meta: ProgramMeta,
+    envs: Vec<(String, String)>,
+    limiter: Limiter,
+    request_fuel: u64,
+    usage: Arc<ProgramUsage>,
+    fuel_exhausted: Notify,
     next_id: AtomicU64,
-    stdout: File,
+    requests: Option<ConcurrencyLimit>,
+    rate_limiter: Option<RateLimiter>,
+    io: ProgramIo,
 }
 
 impl ProxyHandlerInner {
     fn next_req_id(&self) -> u64 {
         self.next_id.fetch_add(1, Ordering::Relaxed)
     }
+
+    fn new_store(&self, req_id: u64, fuel: u64) -> Result<Store<Host>> {
+        let mut builder = WasiCtxBuilder::new();
+
+        builder.stdout(self.io.stdout.clone());
+        builder.stderr(self.io.stderr.clone());
+
+        builder.envs(&self.envs);
+        builder.env("REQUEST_ID", req_id.to_string());
+        preopen_dirs(&mut builder, &self.meta.dirs)?;
+
+        let host = Host {
+            table: ResourceTable::new(),
+            ctx: builder.build(),
+            http: WasiHttpCtx::new(),
+            limiter: self.limiter.clone(),
+        };
+
+        let mut store = Store::new(&self.http_engine.engine, host);
+        store.limiter(|host| &mut host.limiter);
+        store.set_fuel(fuel)?;
+        set_timeout(&mut store, self.meta.request_timeout);
+
+        Ok(store)
+    }
+
+    /// Returns the fuel that the next request can consume, which is bounded
+    /// by both the request budget and what remains of the program budget.
+    ///
+    /// Concurrent requests share the remaining program budget, so the program
+    /// may slightly overrun its budget when several requests are in flight.
+    fn request_fuel(&self) -> u64 {
+        match self.meta.fuel {
+            Some(fuel) => fuel
+                .saturating_sub(self.usage.fuel_consumed.load(Ordering::Relaxed))
+                .min(self.request_fuel),
+            None => self.request_fuel,
+        }
+    }
+
+    fn consume_fuel(&self, fuel: u64) {
+        let consumed = self.usage.fuel_consumed.fetch_add(fuel, Ordering::Relaxed) + fuel;
+        if self.meta.fuel.is_some_and(|budget| consumed >= budget) {
+            self.fuel_exhausted.notify_one();
+        }
+    }
+}
+
+/// Owns the store of a request, and records the fuel it consumed once it's
+/// dropped, including when the request is aborted after timing out.
+struct RequestStore {
+    store: Store<Host>,
+    handler: Arc<ProxyHandlerInner>,
+    fuel: u64,
+}
+
+impl Drop for RequestStore {
+    fn drop(&mut self) {
+        self.handler
+            .consume_fuel(self.fuel - self.store.get_fuel().unwrap_or_default());
+    }
 }
 
 #[derive(Clone)]
-struct ProxyHandler(Arc<ProxyHandlerInner>);
+pub(crate) struct ProxyHandler(Arc<ProxyHandlerInner>);
 
 impl ProxyHandler {
-    fn new(http_engine: HttpEngine, instance_pre: ProxyPre<Host>, stdout: File) -> Self {
-        Self(Arc::new(ProxyHandlerInner {
+    fn new(
+        http_engine: HttpEngine,
+        instance_pre: ProxyPre<Host>,
+        meta: ProgramMeta,
+        usage: Arc<ProgramUsage>,
+        io: ProgramIo,
+    ) -> Result<Self> {
+        Ok(Self(Arc::new(ProxyHandlerInner {
             http_engine,
             instance_pre,
+            envs: envs(&meta),
+            limiter: Limiter::new(&meta),
+            request_fuel: meta.request_fuel.unwrap_or(u64::MAX),
+            requests: meta
+                .max_concurrent_requests
+                .map(|max_requests| ConcurrencyLimit::new(max_requests as usize, meta.request_backlog as usize)),
+            rate_limiter: RateLimiter::new(&meta)?,
+            meta,
+            usage,
+            fuel_exhausted: Notify::new(),
             next_id: AtomicU64::from(0),
-            stdout,
-        }))
+            io,
+        })))
     }
 }
 
 async fn handle_request(
     ProxyHandler(inner): ProxyHandler,
+    tls: bool,
     req: Request<hyper::body::Incoming>,
 ) -> Result<hyper::Response<HyperOutgoingBody>> {
     let (sender, receiver) = tokio::sync::oneshot::channel();
 
     let req_id = inner.next_req_id();
 
-    let mut stdout = inner.stdout.try_clone()?;
-    stdout.write_fmt(format_args!(
+    inner.io.stdout.clone().write_fmt(format_args!(
         "Request {req_id} handling {} to {}\n",
         req.method(),
         req.uri()
     ))?;
 
-    let mut store = inner.http_engine.new_store(req_id, stdout)?;
+    let permit = match &inner.requests {
+        Some(requests) => match requests.acquire().await {
+            Some(permit) => Some(Arc::new(permit)),
+            None => {
+                inner.io.stderr.clone().write_fmt(format_args!(
+                    "Request {req_id} rejected: too many concurrent requests\n"
+                ))?;
+                return Ok(empty_response(StatusCode::SERVICE_UNAVAILABLE));
+            }
+        },
+        None => None,
+    };
+
+    let fuel = inner.request_fuel();
+    if fuel == 0 {
+        inner.io.stderr.clone().write_fmt(format_args!(
+            "Request {req_id} rejected: the fuel budget of the program is exhausted\n"
+        ))?;
+        return Ok(empty_response(StatusCode::SERVICE_UNAVAILABLE));
+    }
 
-    let req = store.data_mut().new_incoming_request(Scheme::Http, req)?;
-    let out = store.data_mut().new_response_outparam(sender)?;
-    let proxy = inner.instance_pre.instantiate_async(&mut store).await?;
+    let mut stderr = inner.io.stderr.clone();
+    let mut store = RequestStore {
+        store: inner.new_store(req_id, fuel)?,
+        handler: inner.clone(),
+        fuel,
+    };
 
+    let scheme = if tls { Scheme::Https } else { Scheme::Http };
+    let req = store.store.data_mut().new_incoming_request(scheme, req)?;
+    let out = store.store.data_mut().new_response_outparam(sender)?;
+    let proxy = inner.instance_pre.instantiate_async(&mut store.store).await?;
+
+    // The request holds its slot until both the handler has returned and the
+    // response body has been sent, since the guest may keep writing the body
+    // after the response is sent.
+    let task_permit = permit.clone();
     let task = tokio::task::spawn(async move {
-        if let Err(e) = proxy.wasi_http_incoming_handler().call_handle(store, req, out).await {
+        let _permit = task_permit;
+        let result = proxy
+            .wasi_http_incoming_handler()
+            .call_handle(&mut store.store, req, out)
+            .await;
+        drop(store);
+
+        if let Err(e) = result {
             log::error!("[{req_id}] :: {:#?}", e);
+            let _ = stderr.write_fmt(format_args!("Request {req_id} error: {e:#}\n"));
             return Err(e);
         }
 
         Ok(())
     });
 
-    match receiver.await {
-        Ok(Ok(resp)) => Ok(resp),
+    let result = match inner.meta.request_timeout {
+        Some(timeout) => match tokio::time::timeout(timeout, receiver).await {
+            Ok(result) => result,
+            Err(_) => {
+                task.abort();
+                inner.io.stderr.clone().write_fmt(format_args!(
+                    "Request {req_id} timed out after {}s\n",
+                    timeout.as_secs()
+                ))?;
+                return Ok(empty_response(StatusCode::GATEWAY_TIMEOUT));
+            }
+        },
+        None => receiver.await,
+    };
+
+    match result {
+        Ok(Ok(resp)) => Ok(match permit {
+            Some(permit) => resp.map(|body| {
+                body.map_frame(move |frame| {
+                    let _ = &permit;
+                    frame
+                })
+                .boxed()
+            }),
+            None => resp,
+        }),
         Ok(Err(e)) => Err(e.into()),
         Err(_) => {
             // An error in the receiver (`RecvError`) only indicates that the
@@ -186,6 +458,61 @@ async fn handle_request(
     }
 }
 
+pub(crate) fn empty_response(status: StatusCode) -> hyper::Response<HyperOutgoingBody> {
+    let mut resp = hyper::Response::new(Empty::new().map_err(|never| match never {}).boxed());
+    *resp.status_mut() = status;
+    resp
+}
+
+/// Responds with 429, telling the client to retry once its next request is
+/// allowed, `Retry-After` is in whole seconds so the wait is rounded up.
+fn too_many_requests(wait: Duration) -> hyper::Response<HyperOutgoingBody> {
+    let mut resp = empty_response(StatusCode::TOO_MANY_REQUESTS);
+    let retry_after = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+    resp.headers_mut().insert(RETRY_AFTER, HeaderValue::from(retry_after));
+    resp
+}
+
+/// Bounds the number of requests handled at the same time, requests beyond
+/// wait for a slot in a bounded backlog, and are rejected once it's full.
+struct ConcurrencyLimit {
+    semaphore: Arc<Semaphore>,
+    backlog: usize,
+    waiting: Arc<AtomicUsize>,
+}
+
+impl ConcurrencyLimit {
+    fn new(max_requests: usize, backlog: usize) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(max_requests)),
+            backlog,
+            waiting: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Waits for a slot, or returns `None` if the backlog is full.
+    async fn acquire(&self) -> Option<OwnedSemaphorePermit> {
+        if let Ok(permit) = self.semaphore.clone().try_acquire_owned() {
+            return Some(permit);
+        }
+        if self.waiting.fetch_add(1, Ordering::Relaxed) >= self.backlog {
+            self.waiting.fetch_sub(1, Ordering::Relaxed);
+            return None;
+        }
+        // The request is dropped if the client goes away while waiting.
+        let _waiting = WaitingGuard(self.waiting.clone());
+        self.semaphore.clone().acquire_owned().await.ok()
+    }
+}
+
+struct WaitingGuard(Arc<AtomicUsize>);
+
+impl Drop for WaitingGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
 // ref: https://github.com/bytecodealliance/wasmtime/blob/ee9e1ca54586516c14d0c4a8dae63691a1d4b50c/src/commands/serve.rs#L561-L597
 
 /// The pooling allocator is tailor made for the `wasmtime serve` use case, so
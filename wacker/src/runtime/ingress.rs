@@ -0,0 +1,169 @@
+use crate::runtime::http::{empty_response, serve_request, ProxyHandler};
+use anyhow::{bail, Result};
+use hyper::{header::HOST, http::uri::Authority, Request, StatusCode};
+use hyper_util::{rt::TokioExecutor, server::conn::auto};
+use log::warn;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use wasmtime_wasi_http::io::TokioIo;
+
+/// A route of the ingress listener, which dispatches the requests of a host
+/// and path prefix to an HTTP program.
+#[derive(Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RouteRule {
+    /// The host of the requests in lowercase, `None` means any host.
+    pub host: Option<String>,
+    /// The path prefix of the requests, which matches whole path segments.
+    pub path: String,
+}
+
+impl RouteRule {
+    fn matches(&self, host: Option<&str>, path: &str) -> bool {
+        if self.host.as_deref().is_some_and(|h| Some(h) != host) {
+            return false;
+        }
+        match path.strip_prefix(self.prefix()) {
+            Some(rest) => rest.is_empty() || rest.starts_with('/'),
+            None => false,
+        }
+    }
+
+    fn prefix(&self) -> &str {
+        self.path.trim_end_matches('/')
+    }
+}
+
+impl fmt::Display for RouteRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.host.as_deref().unwrap_or("*"), self.path)
+    }
+}
+
+/// The routing table of the ingress listener of the daemon, HTTP programs
+/// register their routes while they run.
+#[derive(Default)]
+pub struct Ingress {
+    routes: RwLock<Vec<IngressEntry>>,
+    next_registration: AtomicU64,
+}
+
+struct IngressEntry {
+    rule: RouteRule,
+    id: String,
+    registration: u64,
+    handler: ProxyHandler,
+}
+
+impl Ingress {
+    /// Dispatches the requests of the routes to the handler of the program
+    /// until the returned registration is dropped.
+    pub(crate) fn register(
+        self: &Arc<Self>,
+        id: &str,
+        rules: &[RouteRule],
+        handler: &ProxyHandler,
+    ) -> Result<IngressRegistration> {
+        let registration = self.next_registration.fetch_add(1, Ordering::Relaxed);
+        let mut routes = self.routes.write();
+        // The routes of the same program may not be unregistered yet if it's
+        // restarted, so they are replaced.
+        routes.retain(|entry| entry.id != id);
+        for rule in rules {
+            if let Some(entry) = routes.iter().find(|entry| entry.rule == *rule) {
+                bail!("the route {} is already used by {}", rule, entry.id);
+            }
+        }
+        routes.extend(rules.iter().map(|rule| IngressEntry {
+            rule: rule.clone(),
+            id: id.to_string(),
+            registration,
+            handler: handler.clone(),
+        }));
+        Ok(IngressRegistration {
+            ingress: self.clone(),
+            registration,
+        })
+    }
+
+    /// Returns the handler of the most specific route of the request, routes
+    /// with a host take precedence, then the longest path prefix.
+    fn resolve<B>(&self, req: &Request<B>) -> Option<ProxyHandler> {
+        let host = request_host(req);
+        let path = req.uri().path();
+        self.routes
+            .read()
+            .iter()
+            .filter(|entry| entry.rule.matches(host.as_deref(), path))
+            .max_by_key(|entry| (entry.rule.host.is_some(), entry.rule.prefix().len()))
+            .map(|entry| entry.handler.clone())
+    }
+}
+
+pub(crate) struct IngressRegistration {
+    ingress: Arc<Ingress>,
+    registration: u64,
+}
+
+impl Drop for IngressRegistration {
+    fn drop(&mut self) {
+        self.ingress
+            .routes
+            .write()
+            .retain(|entry| entry.registration != self.registration);
+    }
+}
+
+/// Returns the host of the request without the port, from the URI for
+/// HTTP/2, or from the `Host` header for HTTP/1.1.
+fn request_host<B>(req: &Request<B>) -> Option<String> {
+    let host = match req.uri().host() {
+        Some(host) => host.to_string(),
+        None => {
+            let value = req.headers().get(HOST)?.to_str().ok()?;
+            value.parse::<Authority>().ok()?.host().to_string()
+        }
+    };
+    Some(host.to_ascii_lowercase())
+}
+
+/// Accepts the connections of the ingress listener, and dispatches their
+/// requests to the programs by their routes, or responds with 404 if no
+/// route matches.
+pub async fn serve_ingress(listener: TcpListener, ingress: Arc<Ingress>) {
+    let builder = Arc::new(auto::Builder::new(TokioExecutor::new()));
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(res) => res,
+            Err(err) => {
+                warn!("failed to accept an ingress connection: {}", err);
+                // e.g. too many open files, which may take a while to recover
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        let ingress = ingress.clone();
+        let builder = builder.clone();
+        tokio::task::spawn(async move {
+            let service = hyper::service::service_fn(move |req| {
+                let handler = ingress.resolve(&req);
+                async move {
+                    match handler {
+                        Some(handler) => serve_request(handler, addr.ip(), false, req).await,
+                        None => Ok(empty_response(StatusCode::NOT_FOUND)),
+                    }
+                }
+            });
+            if let Err(err) = builder.serve_connection(TokioIo::new(stream), service).await {
+                warn!("ingress serve error: {:?}", err);
+            }
+        });
+    }
+}
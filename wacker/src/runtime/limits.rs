@@ -0,0 +1,69 @@
+use crate::runtime::ProgramMeta;
+use anyhow::{bail, Result};
+use wasmtime::ResourceLimiter;
+
+// The same defaults as wasmtime uses when no limiter is installed.
+const DEFAULT_INSTANCE_LIMIT: usize = 10000;
+const DEFAULT_TABLE_LIMIT: usize = 10000;
+const DEFAULT_MEMORY_LIMIT: usize = 10000;
+
+/// Enforces the resource limits of a program on its `Store`.
+///
+/// The memory limit applies to each linear memory, and the table elements
+/// limit applies to each table.
+#[derive(Clone, Default)]
+pub struct Limiter {
+    memory: Option<usize>,
+    table_elements: Option<u32>,
+    instances: Option<usize>,
+}
+
+impl Limiter {
+    pub fn new(meta: &ProgramMeta) -> Self {
+        Self {
+            memory: meta.memory_limit.map(|limit| limit as usize),
+            table_elements: meta.table_elements_limit,
+            instances: meta.instances_limit.map(|limit| limit as usize),
+        }
+    }
+}
+
+impl ResourceLimiter for Limiter {
+    fn memory_growing(&mut self, _current: usize, desired: usize, maximum: Option<usize>) -> Result<bool> {
+        if let Some(limit) = self.memory {
+            if desired > limit {
+                bail!(
+                    "memory limit exceeded: growing linear memory to {} bytes, but the limit is {} bytes",
+                    desired,
+                    limit
+                );
+            }
+        }
+        Ok(maximum.map_or(true, |max| desired <= max))
+    }
+
+    fn table_growing(&mut self, _current: u32, desired: u32, maximum: Option<u32>) -> Result<bool> {
+        if let Some(limit) = self.table_elements {
+            if desired > limit {
+                bail!(
+                    "table elements limit exceeded: growing table to {} elements, but the limit is {} elements",
+                    desired,
+                    limit
+                );
+            }
+        }
+        Ok(maximum.map_or(true, |max| desired <= max))
+    }
+
+    fn instances(&self) -> usize {
+        self.instances.unwrap_or(DEFAULT_INSTANCE_LIMIT)
+    }
+
+    fn tables(&self) -> usize {
+        DEFAULT_TABLE_LIMIT
+    }
+
+    fn memories(&self) -> usize {
+        DEFAULT_MEMORY_LIMIT
+    }
+}
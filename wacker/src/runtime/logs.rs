@@ -1,36 +1,817 @@
-use anyhow::Error;
+use crate::{LOG_DRIVER_FILE, LOG_DRIVER_JSON_LINES, LOG_DRIVER_SYSLOG, LOG_STREAM_STDERR, LOG_STREAM_STDOUT};
 use async_trait::async_trait;
-use std::fs::File;
-use std::io::Write;
-use wasmtime_wasi::{HostOutputStream, StdoutStream, StreamError, StreamResult, Subscribe};
+use bytes::Bytes;
+use chrono::{DateTime, Local, SecondsFormat, Utc};
+use log::warn;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::borrow::Cow;
+use std::fs::{self, read_dir, remove_file, rename, File, OpenOptions};
+use std::io::{self, ErrorKind, IoSlice, Read, Write};
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Weak,
+};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncReadExt;
+use tokio::sync::broadcast;
+use wasi_common::file::{FdFlags, FileType};
+use wasmtime_wasi::{HostOutputStream, StdoutStream, StreamResult, Subscribe};
 
-pub struct LogStream {
-    pub output: File,
+/// The maximum number of records buffered for each attached client.
+const OUTPUT_CAPACITY: usize = 1024;
+
+/// The number of log files kept if only the size is set, so that a rotation
+/// doesn't discard the whole history.
+const DEFAULT_MAX_LOG_FILES: u32 = 2;
+
+/// The default address of the syslog socket.
+const DEFAULT_SYSLOG_ADDRESS: &str = "/dev/log";
+
+/// The size at which a line without a newline is sent to syslog anyway.
+const MAX_SYSLOG_LINE: usize = 8192;
+
+/// The length of the header of a record in the log file, which consists of
+/// the stream (1 byte), the time in nanoseconds since the Unix epoch (8 bytes,
+/// big-endian) and the length of the data (4 bytes, big-endian).
+const RECORD_HEADER_LEN: usize = 13;
+
+/// Starts the log files of the file driver, followed by the version of the
+/// format. The log files written before the output was recorded hold the raw
+/// output without a header.
+const LOG_FILE_MAGIC: &[u8] = b"\0wacker-log";
+const LOG_FILE_VERSION: u8 = 1;
+
+/// A chunk of the output of a program, tagged with the stream it was
+/// written to and the time it was written.
+#[derive(Clone)]
+pub struct LogRecord {
+    pub stream: u32,
+    pub time: SystemTime,
+    pub bytes: Bytes,
 }
 
-impl StdoutStream for LogStream {
-    fn stream(&self) -> Box<dyn HostOutputStream> {
-        Box::new(LogStream {
-            output: self.output.try_clone().expect(""),
+impl LogRecord {
+    fn encode(&self) -> Vec<u8> {
+        let nanos = self
+            .time
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_nanos() as u64)
+            .unwrap_or_default();
+        let mut buf = Vec::with_capacity(RECORD_HEADER_LEN + self.bytes.len());
+        buf.push(self.stream as u8);
+        buf.extend_from_slice(&nanos.to_be_bytes());
+        buf.extend_from_slice(&(self.bytes.len() as u32).to_be_bytes());
+        buf.extend_from_slice(&self.bytes);
+        buf
+    }
+
+    /// Decodes the records at the start of `buf`, and returns them along
+    /// with the number of bytes they take, an incomplete record at the end
+    /// is left for the next read.
+    pub fn decode(buf: &[u8]) -> (Vec<LogRecord>, usize) {
+        let mut records = vec![];
+        let mut pos = 0;
+        while let Some(header) = buf.get(pos..pos + RECORD_HEADER_LEN) {
+            let nanos = u64::from_be_bytes(header[1..9].try_into().unwrap());
+            let len = u32::from_be_bytes(header[9..13].try_into().unwrap()) as usize;
+            let Some(bytes) = buf.get(pos + RECORD_HEADER_LEN..pos + RECORD_HEADER_LEN + len) else {
+                break;
+            };
+            records.push(LogRecord {
+                stream: header[0] as u32,
+                time: UNIX_EPOCH + Duration::from_nanos(nanos),
+                bytes: Bytes::copy_from_slice(bytes),
+            });
+            pos += RECORD_HEADER_LEN + len;
+        }
+        (records, pos)
+    }
+
+    /// Decodes the records of a log file of the file driver, a log file
+    /// without a header is read as a single record of stdout written when
+    /// the file was last modified.
+    fn decode_file(contents: &[u8], modified: SystemTime) -> Vec<LogRecord> {
+        match contents.strip_prefix(LOG_FILE_MAGIC) {
+            Some([LOG_FILE_VERSION, records @ ..]) => Self::decode(records).0,
+            // The header is being written, or the file is of a newer version.
+            Some(_) => vec![],
+            None if contents.is_empty() => vec![],
+            None => vec![LogRecord {
+                stream: LOG_STREAM_STDOUT,
+                time: modified,
+                bytes: Bytes::copy_from_slice(contents),
+            }],
+        }
+    }
+}
+
+/// How the records are stored in the log files of a log driver.
+#[derive(Clone, Copy)]
+pub enum LogFormat {
+    /// The binary records of the file driver.
+    Records,
+    /// The lines of JSON of the json-lines driver.
+    JsonLines,
+}
+
+impl LogFormat {
+    /// Decodes the records of a log file, `modified` is the time the file
+    /// was last modified.
+    pub fn decode(self, contents: &[u8], modified: SystemTime) -> Vec<LogRecord> {
+        match self {
+            Self::Records => LogRecord::decode_file(contents, modified),
+            Self::JsonLines => contents
+                .split_inclusive(|byte| *byte == b'\n')
+                .filter(|line| line.ends_with(b"\n"))
+                .filter_map(|line| serde_json::from_slice::<JsonLine>(line).ok()?.to_record())
+                .collect(),
+        }
+    }
+}
+
+/// The log files written so far, from the oldest to the newest, along with
+/// the length of each that has been written.
+pub struct LogFiles {
+    pub format: LogFormat,
+    pub files: Vec<(File, u64)>,
+}
+
+impl LogFiles {
+    /// Reads the records of the log files, up to the length of each that
+    /// had been written.
+    pub async fn read(self) -> io::Result<Vec<LogRecord>> {
+        let mut records = vec![];
+        for (file, len) in self.files {
+            let file = tokio::fs::File::from_std(file);
+            let modified = file.metadata().await?.modified()?;
+            let mut contents = vec![];
+            file.take(len).read_to_end(&mut contents).await?;
+            records.extend(self.format.decode(&contents, modified));
+        }
+        Ok(records)
+    }
+}
+
+/// The header of the log files of the file driver.
+fn log_file_header() -> Vec<u8> {
+    [LOG_FILE_MAGIC, &[LOG_FILE_VERSION]].concat()
+}
+
+/// The rotation of the log file of a program.
+#[derive(Clone, Copy, Default)]
+pub struct LogRotation {
+    /// Rotates the log file once it reaches this size in bytes.
+    pub max_size: Option<u64>,
+    /// The maximum number of log files kept, including the current one, 2 by
+    /// default.
+    pub max_files: Option<u32>,
+}
+
+/// The log settings of a program.
+#[derive(Clone, Default)]
+pub struct LogOptions {
+    /// One of the `LOG_DRIVER_*` constants, the file driver by default.
+    pub driver: Option<u32>,
+    pub rotation: LogRotation,
+    /// The path of the unix datagram socket of the syslog driver.
+    pub syslog_address: Option<String>,
+}
+
+/// Returns the log files of a program from the oldest to the newest, the
+/// rotated segments `<path>.N` come before the current file, and `<path>.1`
+/// is the newest segment.
+pub fn log_files(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files: Vec<_> = rotated_segments(path)?
+        .into_iter()
+        .rev()
+        .map(|(_, path)| path)
+        .collect();
+    files.push(path.to_path_buf());
+    Ok(files)
+}
+
+/// Returns the rotated segments of the log file ordered by their indexes.
+fn rotated_segments(path: &Path) -> io::Result<Vec<(u32, PathBuf)>> {
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return Ok(vec![]);
+    };
+    let prefix = format!("{}.", name.to_string_lossy());
+    let mut segments = vec![];
+    for entry in read_dir(dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        if let Some(index) = file_name
+            .to_str()
+            .and_then(|file_name| file_name.strip_prefix(&prefix))
+            .and_then(|index| index.parse::<u32>().ok())
+        {
+            segments.push((index, entry.path()));
+        }
+    }
+    segments.sort();
+    Ok(segments)
+}
+
+fn segment_path(path: &Path, index: u32) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(format!(".{}", index));
+    PathBuf::from(name)
+}
+
+/// The path of the JSON lines log file of a program.
+fn json_lines_path(logs_dir: &Path, id: &str) -> PathBuf {
+    logs_dir.join(format!("{}.json", id))
+}
+
+/// Removes the log files written by all the drivers for a program.
+pub fn remove_logs(logs_dir: &Path, id: &str) -> io::Result<()> {
+    for path in [logs_dir.join(id), json_lines_path(logs_dir, id)] {
+        for file in log_files(&path)? {
+            if let Err(err) = remove_file(file) {
+                if err.kind() != ErrorKind::NotFound {
+                    return Err(err);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    rotation: LogRotation,
+    /// Written at the start of every new file.
+    header: Vec<u8>,
+}
+
+impl LogFile {
+    fn open(path: &Path, rotation: LogRotation, header: Vec<u8>) -> io::Result<Self> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        let mut size = file.metadata()?.len();
+        if size == 0 {
+            file.write_all(&header)?;
+            size = header.len() as u64;
+        } else if !header.is_empty() && !read_prefix(path, header.len())?.starts_with(&header) {
+            size = convert_legacy_file(path, &header)?;
+            file = OpenOptions::new().append(true).open(path)?;
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            size,
+            rotation,
+            header,
         })
     }
 
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if let Some(max_size) = self.rotation.max_size {
+            if self.size > self.header.len() as u64 && self.size + bytes.len() as u64 > max_size {
+                self.rotate()?;
+            }
+        }
+        self.file.write_all(bytes)?;
+        self.size += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Renames `<path>.N` to `<path>.N+1` and the current file to `<path>.1`,
+    /// and removes the segments beyond the maximum number of files.
+    fn rotate(&mut self) -> io::Result<()> {
+        let max_segments = self.rotation.max_files.unwrap_or(DEFAULT_MAX_LOG_FILES).max(1) - 1;
+        for (index, path) in rotated_segments(&self.path)?.into_iter().rev() {
+            if index >= max_segments {
+                remove_file(path)?;
+            } else {
+                rename(path, segment_path(&self.path, index + 1))?;
+            }
+        }
+        if max_segments > 0 {
+            rename(&self.path, segment_path(&self.path, 1))?;
+        } else {
+            remove_file(&self.path)?;
+        }
+        *self = Self::open(&self.path, self.rotation, std::mem::take(&mut self.header))?;
+        Ok(())
+    }
+
+    /// Opens the current file and its rotated segments from the oldest to the
+    /// newest, along with the length of each that has been written.
+    fn open_files(&self) -> io::Result<Vec<(File, u64)>> {
+        let mut files = vec![];
+        for path in log_files(&self.path)? {
+            let file = File::open(&path)?;
+            let len = match path == self.path {
+                true => self.size,
+                false => file.metadata()?.len(),
+            };
+            files.push((file, len));
+        }
+        Ok(files)
+    }
+}
+
+fn read_prefix(path: &Path, len: usize) -> io::Result<Vec<u8>> {
+    let mut prefix = vec![];
+    File::open(path)?.take(len as u64).read_to_end(&mut prefix)?;
+    Ok(prefix)
+}
+
+/// Rewrites a log file that holds the raw output of a program, written
+/// before the output was recorded, as a single record of stdout after the
+/// header, and returns its new size.
+fn convert_legacy_file(path: &Path, header: &[u8]) -> io::Result<u64> {
+    let contents = fs::read(path)?;
+    let modified = fs::metadata(path)?.modified()?;
+    let mut converted = header.to_vec();
+    converted.extend_from_slice(&LogRecord::decode_file(&contents, modified)[0].encode());
+    let mut tmp_path = path.as_os_str().to_os_string();
+    tmp_path.push(".tmp");
+    fs::write(&tmp_path, &converted)?;
+    rename(&tmp_path, path)?;
+    Ok(converted.len() as u64)
+}
+
+/// Where the output of a program goes.
+pub trait LogDriver: Send {
+    fn write(&mut self, record: &LogRecord) -> io::Result<()>;
+
+    /// Opens the log files written so far, or returns `None` if the logs
+    /// can't be read back.
+    fn open_files(&self) -> io::Result<Option<LogFiles>> {
+        Ok(None)
+    }
+}
+
+/// Opens the log driver of a program.
+pub fn open_driver(logs_dir: &Path, id: &str, options: &LogOptions) -> io::Result<Box<dyn LogDriver>> {
+    match options.driver.unwrap_or(LOG_DRIVER_FILE) {
+        LOG_DRIVER_FILE => Ok(Box::new(FileDriver(LogFile::open(
+            &logs_dir.join(id),
+            options.rotation,
+            log_file_header(),
+        )?))),
+        LOG_DRIVER_JSON_LINES => Ok(Box::new(JsonLinesDriver {
+            id: id.to_string(),
+            file: LogFile::open(&json_lines_path(logs_dir, id), options.rotation, vec![])?,
+        })),
+        // The socket is connected on the first write, so that the program
+        // runs even if syslog is not available yet.
+        LOG_DRIVER_SYSLOG => Ok(Box::new(SyslogDriver {
+            tag: format!("wacker/{}", id),
+            address: options
+                .syslog_address
+                .clone()
+                .unwrap_or_else(|| DEFAULT_SYSLOG_ADDRESS.to_string()),
+            socket: None,
+            dropped: 0,
+            stdout: vec![],
+            stderr: vec![],
+        })),
+        driver => Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("unknown log driver {}", driver),
+        )),
+    }
+}
+
+/// Writes the records to the log file in their binary form, which can be
+/// read back by `wacker logs`.
+struct FileDriver(LogFile);
+
+impl LogDriver for FileDriver {
+    fn write(&mut self, record: &LogRecord) -> io::Result<()> {
+        self.0.write(&record.encode())
+    }
+
+    fn open_files(&self) -> io::Result<Option<LogFiles>> {
+        Ok(Some(LogFiles {
+            format: LogFormat::Records,
+            files: self.0.open_files()?,
+        }))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonLine<'a> {
+    time: String,
+    #[serde(borrow)]
+    stream: Cow<'a, str>,
+    #[serde(borrow)]
+    id: Cow<'a, str>,
+    log: String,
+}
+
+impl JsonLine<'_> {
+    fn to_record(&self) -> Option<LogRecord> {
+        Some(LogRecord {
+            stream: match self.stream.as_ref() {
+                "stderr" => LOG_STREAM_STDERR,
+                _ => LOG_STREAM_STDOUT,
+            },
+            time: DateTime::parse_from_rfc3339(&self.time).ok()?.into(),
+            bytes: Bytes::copy_from_slice(self.log.as_bytes()),
+        })
+    }
+}
+
+/// Writes every record as a line of JSON to `<id>.json`, the output that is
+/// not valid UTF-8 is converted lossily, so are the logs read back.
+struct JsonLinesDriver {
+    id: String,
+    file: LogFile,
+}
+
+impl LogDriver for JsonLinesDriver {
+    fn write(&mut self, record: &LogRecord) -> io::Result<()> {
+        let line = JsonLine {
+            time: DateTime::<Utc>::from(record.time).to_rfc3339_opts(SecondsFormat::Nanos, true),
+            stream: Cow::Borrowed(match record.stream {
+                LOG_STREAM_STDERR => "stderr",
+                _ => "stdout",
+            }),
+            id: Cow::Borrowed(&self.id),
+            log: String::from_utf8_lossy(&record.bytes).into_owned(),
+        };
+        let mut bytes = serde_json::to_vec(&line)?;
+        bytes.push(b'\n');
+        self.file.write(&bytes)
+    }
+
+    fn open_files(&self) -> io::Result<Option<LogFiles>> {
+        Ok(Some(LogFiles {
+            format: LogFormat::JsonLines,
+            files: self.file.open_files()?,
+        }))
+    }
+}
+
+/// Sends every line of the output as a message to a local syslog socket,
+/// e.g. the one of journald, with the facility user and the severity info
+/// for stdout or err for stderr.
+///
+/// The socket is non-blocking, the messages are dropped rather than holding
+/// up the program if syslog can't keep up or is not available, and it's
+/// reconnected if syslog is restarted.
+///
+/// A line written in several parts is sent once it's complete, or once the
+/// driver is dropped.
+struct SyslogDriver {
+    tag: String,
+    address: String,
+    socket: Option<UnixDatagram>,
+    /// The number of messages dropped since the last one that was sent.
+    dropped: u64,
+    /// The partial last line of each stream.
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+}
+
+impl SyslogDriver {
+    fn partial(&mut self, stream: u32) -> &mut Vec<u8> {
+        match stream {
+            LOG_STREAM_STDERR => &mut self.stderr,
+            _ => &mut self.stdout,
+        }
+    }
+
+    fn send_line(&mut self, stream: u32, time: SystemTime, line: &[u8]) {
+        if line.is_empty() {
+            return;
+        }
+        let priority = match stream {
+            LOG_STREAM_STDERR => 11,
+            _ => 14,
+        };
+        let timestamp = DateTime::<Local>::from(time).format("%b %e %H:%M:%S");
+        let mut message = format!("<{}>{} {}: ", priority, timestamp, self.tag).into_bytes();
+        message.extend_from_slice(line);
+        self.send(&message);
+    }
+
+    fn send(&mut self, message: &[u8]) {
+        // A socket that was connected before may be stale, so it's
+        // reconnected once.
+        let mut reconnect = self.socket.is_some();
+        let err = loop {
+            let socket = match self.socket.take() {
+                Some(socket) => socket,
+                None => match connect_syslog(&self.address) {
+                    Ok(socket) => socket,
+                    Err(err) => break err,
+                },
+            };
+            match socket.send(message) {
+                Ok(_) => {
+                    self.socket = Some(socket);
+                    if self.dropped > 0 {
+                        warn!("dropped {} messages of {} to syslog", self.dropped, self.tag);
+                        self.dropped = 0;
+                    }
+                    return;
+                }
+                Err(err) if matches!(err.kind(), ErrorKind::ConnectionRefused | ErrorKind::NotConnected) => {
+                    if !reconnect {
+                        break err;
+                    }
+                    reconnect = false;
+                }
+                // e.g. the buffer of the socket is full.
+                Err(err) => {
+                    self.socket = Some(socket);
+                    break err;
+                }
+            }
+        };
+        if self.dropped == 0 {
+            warn!(
+                "failed to send the logs of {} to syslog {}: {}",
+                self.tag, self.address, err
+            );
+        }
+        self.dropped += 1;
+    }
+}
+
+fn connect_syslog(address: &str) -> io::Result<UnixDatagram> {
+    let socket = UnixDatagram::unbound()?;
+    socket.set_nonblocking(true)?;
+    socket.connect(address)?;
+    Ok(socket)
+}
+
+impl LogDriver for SyslogDriver {
+    fn write(&mut self, record: &LogRecord) -> io::Result<()> {
+        let mut partial = std::mem::take(self.partial(record.stream));
+        let mut lines = record.bytes.split(|byte| *byte == b'\n');
+        // The last part is the start of a line, which is empty if the record
+        // ends with a newline.
+        let last = lines.next_back().unwrap_or_default();
+        for line in lines {
+            partial.extend_from_slice(line);
+            self.send_line(record.stream, record.time, &partial);
+            partial.clear();
+        }
+        partial.extend_from_slice(last);
+        if partial.len() >= MAX_SYSLOG_LINE {
+            self.send_line(record.stream, record.time, &partial);
+            partial.clear();
+        }
+        *self.partial(record.stream) = partial;
+        Ok(())
+    }
+}
+
+impl Drop for SyslogDriver {
+    fn drop(&mut self) {
+        let now = SystemTime::now();
+        for stream in [LOG_STREAM_STDOUT, LOG_STREAM_STDERR] {
+            let partial = std::mem::take(self.partial(stream));
+            self.send_line(stream, now, &partial);
+        }
+    }
+}
+
+/// The output of a program so far and the output written afterwards, with
+/// no record missing or repeated in between.
+pub struct LogSnapshot {
+    /// The log files written so far, or `None` if the log driver of the
+    /// program can't read back its logs.
+    pub files: Option<LogFiles>,
+    pub receiver: LogReceiver,
+}
+
+/// What a `LogReceiver` receives.
+pub enum LogEvent {
+    Records(Vec<LogRecord>),
+    /// The number of records the receiver couldn't keep up with, which are
+    /// lost since the log driver can't read back its logs.
+    Dropped(u64),
+}
+
+/// Receives the output of a program written after it subscribed.
+///
+/// A receiver that lags behind catches up from the log files if the log
+/// driver can read them back.
+pub struct LogReceiver {
+    /// The receiver doesn't keep the writer, so that it's closed once the
+    /// program is deleted.
+    writer: Weak<LogWriter>,
+    receiver: broadcast::Receiver<LogRecord>,
+    /// The time of the last record received, or written before subscribing.
+    last: SystemTime,
+}
+
+impl LogReceiver {
+    /// Receives the next records, or returns `None` once the log writer is
+    /// dropped.
+    pub async fn recv(&mut self) -> Option<LogEvent> {
+        match self.receiver.recv().await {
+            Ok(record) => {
+                self.last = record.time;
+                Some(LogEvent::Records(vec![record]))
+            }
+            Err(broadcast::error::RecvError::Lagged(dropped)) => Some(self.catch_up(dropped).await),
+            Err(broadcast::error::RecvError::Closed) => None,
+        }
+    }
+
+    /// Receives the next records without waiting, or returns `None` if
+    /// there are none.
+    pub async fn try_recv(&mut self) -> Option<LogEvent> {
+        match self.receiver.try_recv() {
+            Ok(record) => {
+                self.last = record.time;
+                Some(LogEvent::Records(vec![record]))
+            }
+            Err(broadcast::error::TryRecvError::Lagged(dropped)) => Some(self.catch_up(dropped).await),
+            Err(_) => None,
+        }
+    }
+
+    /// Reads the records missed since the last one received from the log
+    /// files, and resubscribes from the end of the files.
+    async fn catch_up(&mut self, dropped: u64) -> LogEvent {
+        let Some(writer) = self.writer.upgrade() else {
+            return LogEvent::Dropped(dropped);
+        };
+        let (files, receiver) = match writer.snapshot() {
+            Ok(LogSnapshot {
+                files: Some(files),
+                receiver,
+            }) => (files, receiver),
+            Ok(_) => return LogEvent::Dropped(dropped),
+            Err(err) => {
+                warn!("failed to open the logs of program {}: {}", writer.id, err);
+                return LogEvent::Dropped(dropped);
+            }
+        };
+        match files.read().await {
+            Ok(records) => {
+                let last = self.last;
+                *self = receiver;
+                LogEvent::Records(records.into_iter().filter(|record| record.time > last).collect())
+            }
+            Err(err) => {
+                warn!("failed to read the logs of program {}: {}", writer.id, err);
+                LogEvent::Dropped(dropped)
+            }
+        }
+    }
+}
+
+/// Writes the output of a program to its log driver, and broadcasts it to
+/// the attached clients and the followers of the logs.
+pub struct LogWriter {
+    id: String,
+    state: Mutex<LogWriterState>,
+    sender: broadcast::Sender<LogRecord>,
+    /// Whether the last write to the log driver failed, so that a failing
+    /// driver is only reported once.
+    failing: AtomicBool,
+}
+
+struct LogWriterState {
+    driver: Box<dyn LogDriver>,
+    /// The time of the last record written, the times of the records are
+    /// strictly increasing, so that a receiver can tell where it left off in
+    /// the log files.
+    last: SystemTime,
+}
+
+impl LogWriter {
+    pub fn new(id: &str, driver: Box<dyn LogDriver>) -> Self {
+        let (sender, _) = broadcast::channel(OUTPUT_CAPACITY);
+        Self {
+            id: id.to_string(),
+            state: Mutex::new(LogWriterState {
+                driver,
+                last: SystemTime::now(),
+            }),
+            sender,
+            failing: AtomicBool::new(false),
+        }
+    }
+
+    /// Subscribes to the output written from now on.
+    pub fn subscribe(self: &Arc<Self>) -> LogReceiver {
+        let state = self.state.lock();
+        self.receiver(&state)
+    }
+
+    /// Opens the log files written so far, and subscribes to the output
+    /// written from now on.
+    pub fn snapshot(self: &Arc<Self>) -> io::Result<LogSnapshot> {
+        // Holding the lock keeps writes and rotations out until the files are
+        // opened, the opened files stay readable even if they are rotated.
+        let state = self.state.lock();
+        Ok(LogSnapshot {
+            files: state.driver.open_files()?,
+            receiver: self.receiver(&state),
+        })
+    }
+
+    fn receiver(self: &Arc<Self>, state: &LogWriterState) -> LogReceiver {
+        LogReceiver {
+            writer: Arc::downgrade(self),
+            receiver: self.sender.subscribe(),
+            last: state.last,
+        }
+    }
+
+    /// Writes the output to the log driver and broadcasts it, the errors of
+    /// the log driver are logged rather than returned, the program goes on
+    /// running if its logs can't be written.
+    fn write(&self, stream: u32, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+        let mut state = self.state.lock();
+        let time = SystemTime::now().max(state.last + Duration::from_nanos(1));
+        state.last = time;
+        let record = LogRecord {
+            stream,
+            time,
+            bytes: Bytes::copy_from_slice(bytes),
+        };
+        match state.driver.write(&record) {
+            Ok(()) => self.failing.store(false, Ordering::Relaxed),
+            Err(err) => {
+                if !self.failing.swap(true, Ordering::Relaxed) {
+                    warn!("failed to write the logs of program {}: {}", self.id, err);
+                }
+            }
+        }
+        // Sending under the lock keeps the order of the file for snapshots,
+        // and there may be no receivers.
+        let _ = self.sender.send(record);
+    }
+}
+
+/// The stdout or stderr of a program.
+///
+/// It can be used as the output stream of both WASI preview1 modules and
+/// WASI components.
+#[derive(Clone)]
+pub struct ProgramOutput {
+    stream: u32,
+    writer: Arc<LogWriter>,
+    tty: bool,
+}
+
+impl ProgramOutput {
+    pub fn stdout(writer: Arc<LogWriter>, tty: bool) -> Self {
+        Self {
+            stream: LOG_STREAM_STDOUT,
+            writer,
+            tty,
+        }
+    }
+
+    pub fn stderr(writer: Arc<LogWriter>, tty: bool) -> Self {
+        Self {
+            stream: LOG_STREAM_STDERR,
+            writer,
+            tty,
+        }
+    }
+}
+
+impl Write for ProgramOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(self.stream, buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl StdoutStream for ProgramOutput {
+    fn stream(&self) -> Box<dyn HostOutputStream> {
+        Box::new(self.clone())
+    }
+
     fn isatty(&self) -> bool {
-        false
+        self.tty
     }
 }
 
-impl HostOutputStream for LogStream {
-    fn write(&mut self, bytes: bytes::Bytes) -> StreamResult<()> {
-        self.output
-            .write_all(bytes.as_ref())
-            .map_err(|e| StreamError::LastOperationFailed(Error::from(e)))
+impl HostOutputStream for ProgramOutput {
+    fn write(&mut self, bytes: Bytes) -> StreamResult<()> {
+        self.writer.write(self.stream, bytes.as_ref());
+        Ok(())
     }
 
     fn flush(&mut self) -> StreamResult<()> {
-        self.output
-            .flush()
-            .map_err(|e| StreamError::LastOperationFailed(Error::from(e)))
+        Ok(())
     }
 
     fn check_write(&mut self) -> StreamResult<usize> {
@@ -39,6 +820,82 @@ impl HostOutputStream for LogStream {
 }
 
 #[async_trait]
-impl Subscribe for LogStream {
+impl Subscribe for ProgramOutput {
     async fn ready(&mut self) {}
 }
+
+#[async_trait]
+impl wasi_common::WasiFile for ProgramOutput {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn get_filetype(&self) -> Result<FileType, wasi_common::Error> {
+        match self.tty {
+            true => Ok(FileType::CharacterDevice),
+            false => Ok(FileType::Pipe),
+        }
+    }
+
+    fn isatty(&self) -> bool {
+        self.tty
+    }
+
+    async fn get_fdflags(&self) -> Result<FdFlags, wasi_common::Error> {
+        Ok(FdFlags::APPEND)
+    }
+
+    async fn write_vectored<'a>(&self, bufs: &[IoSlice<'a>]) -> Result<u64, wasi_common::Error> {
+        let bytes: Vec<u8> = bufs.iter().flat_map(|buf| buf.iter().copied()).collect();
+        self.writer.write(self.stream, &bytes);
+        Ok(bytes.len() as u64)
+    }
+
+    async fn writable(&self) -> Result<(), wasi_common::Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_syslog_partial_lines() {
+        let address = std::env::temp_dir().join(format!("wacker-syslog-{}.sock", std::process::id()));
+        let _ = remove_file(&address);
+        let socket = UnixDatagram::bind(&address).unwrap();
+        socket.set_nonblocking(true).unwrap();
+
+        let mut driver = SyslogDriver {
+            tag: "wacker/test".to_string(),
+            address: address.to_str().unwrap().to_string(),
+            socket: None,
+            dropped: 0,
+            stdout: vec![],
+            stderr: vec![],
+        };
+        for (stream, bytes) in [
+            (LOG_STREAM_STDOUT, "hel"),
+            (LOG_STREAM_STDERR, "err\n"),
+            (LOG_STREAM_STDOUT, "lo\n\nwor"),
+        ] {
+            let record = LogRecord {
+                stream,
+                time: SystemTime::now(),
+                bytes: Bytes::from(bytes),
+            };
+            driver.write(&record).unwrap();
+        }
+        drop(driver);
+
+        let mut messages = vec![];
+        let mut buf = [0; 1024];
+        while let Ok(len) = socket.recv(&mut buf) {
+            let message = String::from_utf8(buf[..len].to_vec()).unwrap();
+            messages.push(message.split_once("wacker/test: ").unwrap().1.to_string());
+        }
+        assert_eq!(messages, ["err", "hello", "wor"]);
+        remove_file(&address).unwrap();
+    }
+}
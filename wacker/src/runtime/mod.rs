@@ -1,35 +1,169 @@
 mod cli;
+mod dir;
 mod host;
 mod http;
+mod ingress;
+mod limits;
 mod logs;
+mod rate_limit;
+mod stdin;
+mod tls;
+
+pub use self::ingress::{serve_ingress, Ingress, RouteRule};
+pub use self::logs::{
+    open_driver, remove_logs, LogEvent, LogFiles, LogOptions, LogReceiver, LogRecord, LogRotation, LogSnapshot,
+    LogWriter, ProgramOutput,
+};
+pub use self::stdin::ProgramStdin;
 
 use crate::{PROGRAM_TYPE_CLI, PROGRAM_TYPE_HTTP};
-use anyhow::Result;
+use anyhow::{anyhow, Error, Result};
 use async_trait::async_trait;
 use hashbrown::HashMap;
-use serde::{Deserialize, Serialize};
+use parking_lot::Mutex;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::fs;
-use std::fs::File;
-use std::sync::Arc;
-use wasmtime::Config;
+use std::path::PathBuf;
+use std::sync::{atomic::AtomicU64, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+use wasmtime::{Config, Store, Trap, UpdateDeadline};
+
+/// How often the epochs of the engines are incremented.
+const EPOCH_TICK: Duration = Duration::from_millis(100);
+
+/// Prefixes the records stored in the db, legacy records are encoded with
+/// bincode and start with the length of the path or the status of a program
+/// instead, which can't be all ones.
+const RECORD_MAGIC: [u8; 8] = [0xff; 8];
+/// The version of the format of the stored records.
+const RECORD_VERSION: u8 = 1;
+
+/// Encodes a record to be stored in the db, fields that are added later
+/// default when older records are decoded.
+pub fn encode_record<T: Serialize>(value: &T) -> Result<Vec<u8>> {
+    let mut bytes = [&RECORD_MAGIC[..], &[RECORD_VERSION]].concat();
+    serde_json::to_writer(&mut bytes, value)?;
+    Ok(bytes)
+}
+
+/// Decodes a record stored in the db, or returns `None` if it's a legacy
+/// record.
+pub fn decode_record<T: DeserializeOwned>(bytes: &[u8]) -> Option<Result<T>> {
+    let bytes = bytes.strip_prefix(&RECORD_MAGIC[..])?;
+    Some(match bytes.split_first() {
+        Some((&RECORD_VERSION, payload)) => serde_json::from_slice(payload).map_err(Error::from),
+        Some((version, _)) => Err(anyhow!("unknown record version {}", version)),
+        None => Err(anyhow!("missing record version")),
+    })
+}
 
 #[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ProgramMeta {
     pub path: String,
     pub program_type: u32,
     pub addr: Option<String>,
     pub args: Vec<String>,
+    pub memory_limit: Option<u64>,
+    pub table_elements_limit: Option<u32>,
+    pub instances_limit: Option<u32>,
+    pub fuel: Option<u64>,
+    pub request_fuel: Option<u64>,
+    pub timeout: Option<Duration>,
+    pub request_timeout: Option<Duration>,
+    pub http2_only: bool,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub max_connections: Option<u32>,
+    pub max_concurrent_requests: Option<u32>,
+    pub request_backlog: u32,
+    pub rate_limit: Option<u32>,
+    pub rate_limit_burst: Option<u32>,
+    pub rate_limit_header: Option<String>,
+    pub routes: Vec<RouteRule>,
+    pub dirs: Vec<PreopenDir>,
+    pub env: Vec<(String, String)>,
+    pub inherit_env: bool,
+    pub restart_policy: u32,
+    pub max_retries: Option<u32>,
+    pub interactive: bool,
+    pub tty: bool,
+    pub log_max_size: Option<u64>,
+    pub log_max_files: Option<u32>,
+    pub log_driver: Option<u32>,
+    pub syslog_address: Option<String>,
+    pub labels: Vec<(String, String)>,
+}
+
+/// The metadata stored by the versions of the daemon before it was versioned.
+#[derive(Deserialize)]
+struct LegacyProgramMeta {
+    path: String,
+    program_type: u32,
+    addr: Option<String>,
+    args: Vec<String>,
+}
+
+impl ProgramMeta {
+    /// Decodes the metadata stored in the db, including the legacy records.
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        if let Some(meta) = decode_record(bytes) {
+            return meta;
+        }
+        let legacy: LegacyProgramMeta = bincode::deserialize(bytes)?;
+        Ok(Self {
+            path: legacy.path,
+            program_type: legacy.program_type,
+            addr: legacy.addr,
+            args: legacy.args,
+            ..Default::default()
+        })
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PreopenDir {
+    pub host_path: String,
+    pub guest_path: String,
+    pub read_only: bool,
+    /// The name of the volume, if the directory is a named volume.
+    pub volume: Option<String>,
+}
+
+/// Resource usage of a program and the module it runs, updated by the
+/// engine while it runs.
+#[derive(Default)]
+pub struct ProgramUsage {
+    pub fuel_consumed: AtomicU64,
+    pub module_hash: Mutex<Option<String>>,
+}
+
+/// The standard streams of a program.
+#[derive(Clone)]
+pub struct ProgramIo {
+    pub stdin: ProgramStdin,
+    pub stdout: ProgramOutput,
+    pub stderr: ProgramOutput,
 }
 
 #[async_trait]
 pub trait Engine: Send + Sync + 'static {
-    async fn run(&self, meta: ProgramMeta, stdout: File) -> Result<()>;
+    /// Runs the program with the ID, and returns its exit code.
+    async fn run(&self, id: &str, meta: ProgramMeta, io: ProgramIo, usage: Arc<ProgramUsage>) -> Result<i32>;
+
+    /// Describes the wasmtime configuration of the engine.
+    fn config(&self) -> Vec<(String, String)>;
 }
 
-pub fn new_engines() -> Result<HashMap<u32, Arc<dyn Engine>>> {
+/// Creates the engines of each program type, HTTP programs register their
+/// routes on the ingress if there is one.
+pub fn new_engines(ingress: Option<Arc<Ingress>>) -> Result<HashMap<u32, Arc<dyn Engine>>> {
     let config = default_wasmtime_config()?;
     let cli_engine: Arc<dyn Engine> = Arc::new(cli::CliEngine::new(&config)?);
-    let http_engine: Arc<dyn Engine> = Arc::new(http::HttpEngine::new(&config)?);
+    let http_engine: Arc<dyn Engine> = Arc::new(http::HttpEngine::new(&config, ingress)?);
 
     Ok(HashMap::from([
         (PROGRAM_TYPE_CLI, cli_engine),
@@ -37,18 +171,105 @@ pub fn new_engines() -> Result<HashMap<u32, Arc<dyn Engine>>> {
     ]))
 }
 
-fn default_wasmtime_config() -> Result<Config> {
-    let mut config = Config::new();
+/// Returns the environment variables of the program, which only include the
+/// environment of the daemon if the program inherits it.
+fn envs(meta: &ProgramMeta) -> Vec<(String, String)> {
+    let mut envs: Vec<(String, String)> = match meta.inherit_env {
+        true => std::env::vars()
+            .filter(|(key, _)| !meta.env.iter().any(|(k, _)| k == key))
+            .collect(),
+        false => vec![],
+    };
+    envs.extend(meta.env.iter().cloned());
+    envs
+}
+
+/// A setting of the default wasmtime configuration, its name and value as
+/// reported by `inspect`, and how it's applied.
+type ConfigSetting = (&'static str, &'static str, fn(&mut Config) -> Result<()>);
+
+/// The settings of `default_wasmtime_config`, which both configures the
+/// engines and describes them from this table.
+const DEFAULT_CONFIG_SETTINGS: &[ConfigSetting] = &[
     // We need this engine's `Store`s to be async, and consume fuel, so
     // that they can co-operatively yield during execution.
-    config.async_support(true);
-    config.consume_fuel(true);
-    config.cache_config_load_default()?;
-    config.cranelift_opt_level(wasmtime::OptLevel::SpeedAndSize);
-    config.wasm_component_model(true);
+    ("async_support", "true", |config| {
+        config.async_support(true);
+        Ok(())
+    }),
+    ("consume_fuel", "true", |config| {
+        config.consume_fuel(true);
+        Ok(())
+    }),
+    // Epoch interruption is used to enforce the wall-clock timeouts.
+    ("epoch_interruption", "true", |config| {
+        config.epoch_interruption(true);
+        Ok(())
+    }),
+    ("cache_config", "default", |config| {
+        config.cache_config_load_default()?;
+        Ok(())
+    }),
+    ("cranelift_opt_level", "speed_and_size", |config| {
+        config.cranelift_opt_level(wasmtime::OptLevel::SpeedAndSize);
+        Ok(())
+    }),
+    ("wasm_component_model", "true", |config| {
+        config.wasm_component_model(true);
+        Ok(())
+    }),
+];
+
+/// Describes the settings of `default_wasmtime_config`.
+fn default_config_settings() -> Vec<(String, String)> {
+    DEFAULT_CONFIG_SETTINGS
+        .iter()
+        .map(|(key, value, _)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+fn default_wasmtime_config() -> Result<Config> {
+    let mut config = Config::new();
+    for (_, _, apply) in DEFAULT_CONFIG_SETTINGS {
+        apply(&mut config)?;
+    }
     Ok(config)
 }
 
+/// Increments the epoch of the engine periodically until the engine is dropped.
+fn spawn_epoch_ticker(engine: &wasmtime::Engine) {
+    let engine = engine.weak();
+    thread::spawn(move || {
+        while let Some(engine) = engine.upgrade() {
+            engine.increment_epoch();
+            drop(engine);
+            thread::sleep(EPOCH_TICK);
+        }
+    });
+}
+
+fn timeout_error(timeout: Duration) -> Error {
+    Error::from(Trap::Interrupt).context(format!("timed out after {}s", timeout.as_secs()))
+}
+
+/// Makes the guest yield on every epoch tick, and traps it once the timeout
+/// has elapsed.
+fn set_timeout<T>(store: &mut Store<T>, timeout: Option<Duration>) {
+    let deadline = timeout.map(|timeout| (Instant::now() + timeout, timeout));
+    store.set_epoch_deadline(1);
+    store.epoch_deadline_callback(move |_| match deadline {
+        Some((deadline, timeout)) if Instant::now() >= deadline => Err(timeout_error(timeout)),
+        _ => Ok(UpdateDeadline::Yield(1)),
+    });
+}
+
+/// Reads the module of the program, and records its hash.
+async fn read_module(path: &str, usage: &ProgramUsage) -> Result<Vec<u8>> {
+    let bytes = read(path).await?;
+    *usage.module_hash.lock() = Some(format!("sha256:{:x}", Sha256::digest(&bytes)));
+    Ok(bytes)
+}
+
 async fn read(path: &str) -> Result<Vec<u8>> {
     match path.starts_with("http") {
         true => {
@@ -0,0 +1,131 @@
+use crate::runtime::ProgramMeta;
+use anyhow::Result;
+use hashbrown::HashMap;
+use hyper::{header::HeaderName, Request};
+use parking_lot::Mutex;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// How often the buckets of the clients that have not been seen for a while
+/// are dropped.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The maximum number of buckets kept, the least recently used bucket is
+/// dropped for a new client beyond.
+const MAX_BUCKETS: usize = 10_000;
+
+/// Limits the rate of the requests of each client of an HTTP program with a
+/// token bucket, clients are identified by their IP address, or by the value
+/// of a request header if it's configured and present.
+///
+/// The first request of a new value of the header also takes a token from
+/// the bucket of the IP address, so that changing the value of the header
+/// doesn't get around the limit.
+pub struct RateLimiter {
+    /// Tokens added to each bucket per second.
+    rate: f64,
+    /// Capacity of each bucket.
+    burst: f64,
+    header: Option<HeaderName>,
+    state: Mutex<RateLimiterState>,
+}
+
+struct RateLimiterState {
+    buckets: HashMap<ClientKey, Bucket>,
+    pruned: Instant,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum ClientKey {
+    Ip(IpAddr),
+    Header(Vec<u8>),
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    /// Creates the rate limiter of an HTTP program, or returns `None` if its
+    /// requests are not rate limited.
+    pub fn new(meta: &ProgramMeta) -> Result<Option<Self>> {
+        let Some(rate) = meta.rate_limit else {
+            return Ok(None);
+        };
+        let header = match &meta.rate_limit_header {
+            Some(header) => Some(HeaderName::from_bytes(header.as_bytes())?),
+            None => None,
+        };
+        Ok(Some(Self {
+            rate: rate as f64,
+            burst: meta.rate_limit_burst.unwrap_or(rate) as f64,
+            header,
+            state: Mutex::new(RateLimiterState {
+                buckets: HashMap::new(),
+                pruned: Instant::now(),
+            }),
+        }))
+    }
+
+    /// Takes a token from the bucket of the client of the request, or returns
+    /// how long the client has to wait for the next token.
+    pub fn check<B>(&self, ip: IpAddr, req: &Request<B>) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut state = self.state.lock();
+        if now.duration_since(state.pruned) >= PRUNE_INTERVAL {
+            self.prune(&mut state, now);
+        }
+
+        let key = match self.header.as_ref().and_then(|header| req.headers().get(header)) {
+            Some(value) => {
+                let key = ClientKey::Header(value.as_bytes().to_vec());
+                if !state.buckets.contains_key(&key) {
+                    self.take(&mut state, ClientKey::Ip(ip), now)?;
+                }
+                key
+            }
+            None => ClientKey::Ip(ip),
+        };
+        self.take(&mut state, key, now)
+    }
+
+    /// Takes a token from the bucket of the client.
+    fn take(&self, state: &mut RateLimiterState, key: ClientKey, now: Instant) -> Result<(), Duration> {
+        if !state.buckets.contains_key(&key) && state.buckets.len() >= MAX_BUCKETS {
+            self.prune(state, now);
+            if state.buckets.len() >= MAX_BUCKETS {
+                let oldest = state
+                    .buckets
+                    .iter()
+                    .min_by_key(|(_, bucket)| bucket.updated)
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    state.buckets.remove(&oldest);
+                }
+            }
+        }
+
+        let bucket = state.buckets.entry(key).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        bucket.tokens = (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * self.rate).min(self.burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+        }
+    }
+
+    fn prune(&self, state: &mut RateLimiterState, now: Instant) {
+        state.pruned = now;
+        // A bucket that would be full again is the same as no bucket.
+        let full = Duration::from_secs_f64(self.burst / self.rate);
+        state
+            .buckets
+            .retain(|_, bucket| now.duration_since(bucket.updated) < full);
+    }
+}
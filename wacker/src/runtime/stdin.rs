@@ -0,0 +1,139 @@
+use async_trait::async_trait;
+use bytes::{Buf, Bytes, BytesMut};
+use std::any::Any;
+use std::io::IoSliceMut;
+use std::sync::Arc;
+use tokio::sync::{mpsc, Mutex};
+use wasi_common::file::FileType;
+use wasmtime_wasi::{HostInputStream, StdinStream, StreamError, StreamResult, Subscribe};
+
+/// The maximum number of chunks buffered in the stdin of a program.
+const STDIN_CAPACITY: usize = 128;
+
+struct Pipe {
+    receiver: mpsc::Receiver<Bytes>,
+    pending: BytesMut,
+    closed: bool,
+}
+
+impl Pipe {
+    /// Waits until there is data to read or the pipe is closed.
+    async fn fill(&mut self) {
+        if self.pending.is_empty() && !self.closed {
+            match self.receiver.recv().await {
+                Some(bytes) => self.pending.extend_from_slice(&bytes),
+                None => self.closed = true,
+            }
+        }
+    }
+}
+
+/// The stdin of a program, fed by the daemon through a pipe.
+///
+/// It is shared by all the runs of a program, and can be used as the input
+/// stream of both WASI preview1 modules and WASI components. The program
+/// reaches the end of its stdin once all the senders of the pipe are dropped.
+#[derive(Clone)]
+pub struct ProgramStdin {
+    pipe: Arc<Mutex<Pipe>>,
+    tty: bool,
+}
+
+impl ProgramStdin {
+    /// Creates the stdin of a program and the sender to write to it.
+    pub fn pipe(tty: bool) -> (mpsc::Sender<Bytes>, Self) {
+        let (sender, receiver) = mpsc::channel(STDIN_CAPACITY);
+        let pipe = Pipe {
+            receiver,
+            pending: BytesMut::new(),
+            closed: false,
+        };
+        (
+            sender,
+            Self {
+                pipe: Arc::new(Mutex::new(pipe)),
+                tty,
+            },
+        )
+    }
+}
+
+impl StdinStream for ProgramStdin {
+    fn stream(&self) -> Box<dyn HostInputStream> {
+        Box::new(self.clone())
+    }
+
+    fn isatty(&self) -> bool {
+        self.tty
+    }
+}
+
+#[async_trait]
+impl HostInputStream for ProgramStdin {
+    fn read(&mut self, size: usize) -> StreamResult<Bytes> {
+        let mut pipe = self
+            .pipe
+            .try_lock()
+            .map_err(|_| StreamError::trap("concurrent reads are not supported"))?;
+        if pipe.pending.is_empty() {
+            match pipe.receiver.try_recv() {
+                Ok(bytes) => pipe.pending.extend_from_slice(&bytes),
+                Err(mpsc::error::TryRecvError::Empty) => {}
+                Err(mpsc::error::TryRecvError::Disconnected) => pipe.closed = true,
+            }
+        }
+        if pipe.pending.is_empty() && pipe.closed {
+            return Err(StreamError::Closed);
+        }
+        let size = size.min(pipe.pending.len());
+        Ok(pipe.pending.split_to(size).freeze())
+    }
+}
+
+#[async_trait]
+impl Subscribe for ProgramStdin {
+    async fn ready(&mut self) {
+        self.pipe.lock().await.fill().await;
+    }
+}
+
+#[async_trait]
+impl wasi_common::WasiFile for ProgramStdin {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn get_filetype(&self) -> Result<FileType, wasi_common::Error> {
+        match self.tty {
+            true => Ok(FileType::CharacterDevice),
+            false => Ok(FileType::Pipe),
+        }
+    }
+
+    fn isatty(&self) -> bool {
+        self.tty
+    }
+
+    async fn read_vectored<'a>(&self, bufs: &mut [IoSliceMut<'a>]) -> Result<u64, wasi_common::Error> {
+        let mut pipe = self.pipe.lock().await;
+        pipe.fill().await;
+
+        let mut read = 0;
+        for buf in bufs.iter_mut() {
+            let size = buf.len().min(pipe.pending.len());
+            buf[..size].copy_from_slice(&pipe.pending[..size]);
+            pipe.pending.advance(size);
+            read += size;
+        }
+        Ok(read as u64)
+    }
+
+    fn num_ready_bytes(&self) -> Result<u64, wasi_common::Error> {
+        Ok(self.pipe.try_lock().map_or(0, |pipe| pipe.pending.len() as u64))
+    }
+
+    async fn readable(&self) -> Result<(), wasi_common::Error> {
+        self.pipe.lock().await.fill().await;
+        Ok(())
+    }
+}
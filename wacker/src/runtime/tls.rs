@@ -0,0 +1,131 @@
+use crate::runtime::{ProgramMeta, ProgramOutput};
+use anyhow::{anyhow, Context, Result};
+use parking_lot::Mutex;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio_rustls::{
+    rustls::{
+        crypto::{ring, CryptoProvider},
+        server::{ClientHello, ResolvesServerCert},
+        sign::CertifiedKey,
+        ServerConfig,
+    },
+    TlsAcceptor,
+};
+
+/// How often the certificate files are checked for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Creates the TLS acceptor of an HTTP program from the certificate and key
+/// files of its metadata, or returns `None` if it's served over plain TCP.
+pub fn tls_acceptor(meta: &ProgramMeta, stderr: ProgramOutput) -> Result<Option<TlsAcceptor>> {
+    let (Some(cert_path), Some(key_path)) = (&meta.tls_cert, &meta.tls_key) else {
+        return Ok(None);
+    };
+    let provider = Arc::new(ring::default_provider());
+    let resolver = CertResolver::new(cert_path, key_path, provider.clone(), stderr)?;
+    let mut config = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_cert_resolver(Arc::new(resolver));
+    config.alpn_protocols = match meta.http2_only {
+        true => vec![b"h2".to_vec()],
+        false => vec![b"h2".to_vec(), b"http/1.1".to_vec()],
+    };
+    Ok(Some(TlsAcceptor::from(Arc::new(config))))
+}
+
+/// Resolves the certificate of an HTTP program from PEM files, and reloads
+/// them once they are modified, so the certificate can be renewed without
+/// restarting the program.
+struct CertResolver {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    provider: Arc<CryptoProvider>,
+    state: Mutex<CertState>,
+}
+
+struct CertState {
+    key: Arc<CertifiedKey>,
+    /// The modification times of the certificate and key files when loaded.
+    modified: (Option<SystemTime>, Option<SystemTime>),
+    checked: Instant,
+    stderr: ProgramOutput,
+}
+
+impl fmt::Debug for CertResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CertResolver")
+            .field("cert_path", &self.cert_path)
+            .field("key_path", &self.key_path)
+            .finish()
+    }
+}
+
+impl CertResolver {
+    fn new(cert_path: &Path, key_path: &Path, provider: Arc<CryptoProvider>, stderr: ProgramOutput) -> Result<Self> {
+        let modified = (modified(cert_path), modified(key_path));
+        let key = load_certified_key(cert_path, key_path, &provider)?;
+        Ok(Self {
+            cert_path: cert_path.to_path_buf(),
+            key_path: key_path.to_path_buf(),
+            provider,
+            state: Mutex::new(CertState {
+                key: Arc::new(key),
+                modified,
+                checked: Instant::now(),
+                stderr,
+            }),
+        })
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let mut state = self.state.lock();
+        if state.checked.elapsed() >= RELOAD_INTERVAL {
+            state.checked = Instant::now();
+            let modified = (modified(&self.cert_path), modified(&self.key_path));
+            if modified != state.modified {
+                state.modified = modified;
+                // Keep serving the old certificate if the new one is invalid,
+                // e.g. only one of the files has been written.
+                match load_certified_key(&self.cert_path, &self.key_path, &self.provider) {
+                    Ok(key) => state.key = Arc::new(key),
+                    Err(e) => {
+                        let _ = state
+                            .stderr
+                            .write_fmt(format_args!("failed to reload the TLS certificate: {e:#}\n"));
+                    }
+                }
+            }
+        }
+        Some(state.key.clone())
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+fn load_certified_key(cert_path: &Path, key_path: &Path, provider: &CryptoProvider) -> Result<CertifiedKey> {
+    let mut reader = BufReader::new(
+        File::open(cert_path).with_context(|| format!("failed to open the certificate {}", cert_path.display()))?,
+    );
+    let certs = rustls_pemfile::certs(&mut reader).collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        return Err(anyhow!("no certificate found in {}", cert_path.display()));
+    }
+
+    let mut reader = BufReader::new(
+        File::open(key_path).with_context(|| format!("failed to open the private key {}", key_path.display()))?,
+    );
+    let key = rustls_pemfile::private_key(&mut reader)?
+        .ok_or_else(|| anyhow!("no private key found in {}", key_path.display()))?;
+    let key = provider.key_provider.load_private_key(key)?;
+    Ok(CertifiedKey::new(certs, key))
+}
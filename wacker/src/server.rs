@@ -1,104 +1,427 @@
 use crate::proto::wacker_server::Wacker;
-use crate::runtime::{new_engines, Engine, ProgramMeta};
+use crate::runtime::{
+    decode_record, encode_record, new_engines, open_driver, remove_logs, Engine, Ingress, LogEvent, LogFiles,
+    LogOptions, LogReceiver, LogRecord, LogRotation, LogSnapshot, LogWriter, PreopenDir, ProgramIo, ProgramMeta,
+    ProgramOutput, ProgramStdin, ProgramUsage, RouteRule,
+};
 use crate::utils::generate_random_string;
 use crate::{
-    DeleteRequest, ListResponse, LogRequest, LogResponse, Program, ProgramResponse, RestartRequest, RunRequest,
-    ServeRequest, StopRequest, PROGRAM_STATUS_ERROR, PROGRAM_STATUS_FINISHED, PROGRAM_STATUS_RUNNING,
-    PROGRAM_STATUS_STOPPED, PROGRAM_TYPE_CLI, PROGRAM_TYPE_HTTP,
+    attach_request, attach_response, AttachRequest, AttachResponse, CreateVolumeRequest, DeleteRequest, DirMount,
+    IngressRoute, InspectRequest, InspectResponse, InspectVolumeRequest, ListResponse, ListRoutesResponse,
+    ListVolumesResponse, LogConfig, LogRequest, LogResponse, Program, ProgramDetails, ProgramResponse, RateLimit,
+    RemoveVolumeRequest, ResourceLimits, RestartPolicy, RestartRequest, Route, RunRequest, ServeRequest, StopRequest,
+    Volume, VolumeMount, WaitRequest, WaitResponse, LOG_DRIVER_SYSLOG, LOG_STREAM_STDERR, PROGRAM_STATUS_ERROR,
+    PROGRAM_STATUS_FINISHED, PROGRAM_STATUS_FUEL_EXHAUSTED, PROGRAM_STATUS_RESTARTING, PROGRAM_STATUS_RUNNING,
+    PROGRAM_STATUS_STOPPED, PROGRAM_STATUS_TIMED_OUT, PROGRAM_TYPE_CLI, PROGRAM_TYPE_HTTP, RESTART_POLICY_ALWAYS,
+    RESTART_POLICY_ON_FAILURE, RESTART_POLICY_UNLESS_STOPPED,
 };
 use anyhow::{anyhow, Error, Result};
-use async_stream::try_stream;
 use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::{DateTime, SecondsFormat, Utc};
 use hashbrown::HashMap;
+use hyper::{header::HeaderName, http::uri::Authority};
 use log::{error, info, warn};
 use parking_lot::Mutex;
 use rayon::prelude::*;
-use sled::Db;
+use regex::bytes::Regex;
+use serde::{Deserialize, Serialize};
+use sled::{Db, Tree};
 use std::fmt::Display;
-use std::fs::{remove_file, OpenOptions};
-use std::io::{ErrorKind, SeekFrom, Write};
-use std::path::{Path, PathBuf};
+use std::fs::{create_dir, read_dir, remove_dir_all};
+use std::future;
+use std::io::{ErrorKind, Write};
+use std::path::{absolute, Path, PathBuf};
 use std::pin::Pin;
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::{atomic::Ordering, Arc};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::{
-    fs::File,
-    io::{AsyncReadExt, AsyncSeekExt},
-    sync::{mpsc, oneshot, oneshot::error::TryRecvError},
+    sync::{mpsc, watch},
     task, time,
 };
-use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
-use tonic::{Request, Response, Status};
+use tokio_stream::{wrappers::ReceiverStream, Stream};
+use tonic::{Request, Response, Status, Streaming};
+use wasmtime::Trap;
 
 pub struct Server {
     db: Db,
     engines: HashMap<u32, Arc<dyn Engine>>,
     programs: Arc<Mutex<HashMap<String, InnerProgram>>>,
+    states: Tree,
     logs_dir: PathBuf,
+    volumes_dir: PathBuf,
+    /// The default rotation of the log files.
+    log_options: LogOptions,
+    /// Whether the daemon has an ingress listener for the routes of programs.
+    ingress: bool,
 }
 
+/// The initial delay before restarting a program.
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+/// The maximum delay before restarting a program.
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(60);
+/// The restart delay is reset if the program ran at least this long.
+const RESTART_DELAY_RESET: Duration = Duration::from_secs(10);
+
 struct InnerProgram {
     id: String,
     meta: ProgramMeta,
-    receiver: oneshot::Receiver<Error>,
-    handler: task::JoinHandle<()>,
+    handler: Option<task::JoinHandle<()>>,
+    state: Arc<StateHandle>,
+    usage: Arc<ProgramUsage>,
+    log: Arc<LogWriter>,
+    /// The sender of the stdin pipe, which is kept open for interactive
+    /// programs.
+    stdin: Option<mpsc::Sender<Bytes>>,
+}
+
+impl InnerProgram {
+    fn is_running(&self) -> bool {
+        self.handler.as_ref().is_some_and(|handler| !handler.is_finished())
+    }
+
+    /// Aborts the supervisor of the program, which may update the state no
+    /// more even if it has yet to stop.
+    fn abort(&self) {
+        self.state.retire();
+        if let Some(handler) = &self.handler {
+            handler.abort();
+        }
+    }
+}
+
+/// The state of a program, persisted in the db so that it survives restarts
+/// of the daemon.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct ProgramState {
     status: u32,
-    error: Option<Error>,
+    exit_code: Option<i32>,
+    error: Option<String>,
+    restart_count: u32,
+    created_at: SystemTime,
+    started_at: Option<SystemTime>,
+    finished_at: Option<SystemTime>,
+}
+
+impl ProgramState {
+    fn new() -> Self {
+        Self {
+            status: PROGRAM_STATUS_RUNNING,
+            exit_code: None,
+            error: None,
+            restart_count: 0,
+            created_at: SystemTime::now(),
+            started_at: None,
+            finished_at: None,
+        }
+    }
+
+    /// Decodes the state stored in the db, including the legacy records.
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        match decode_record(bytes) {
+            Some(state) => state,
+            None => Ok(bincode::deserialize(bytes)?),
+        }
+    }
+}
+
+impl Default for ProgramState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Shares the state of a program between its supervisor and the server, and
+/// writes every change of it to the db.
+struct StateHandle {
+    id: String,
+    sender: watch::Sender<ProgramState>,
+    states: Tree,
+    /// Whether the supervisor of the program was aborted, an aborted task
+    /// runs on until its next await point, so its updates are dropped.
+    retired: Mutex<bool>,
+}
+
+impl StateHandle {
+    fn new(id: &str, state: ProgramState, states: Tree) -> Self {
+        Self {
+            id: id.to_string(),
+            sender: watch::Sender::new(state),
+            states,
+            retired: Mutex::new(false),
+        }
+    }
+
+    fn retire(&self) {
+        *self.retired.lock() = true;
+    }
+
+    /// Updates the state on behalf of the supervisor, unless it was aborted.
+    fn update_running(&self, modify: impl FnOnce(&mut ProgramState)) {
+        let retired = self.retired.lock();
+        if !*retired {
+            self.write(modify);
+        }
+    }
+
+    fn borrow(&self) -> watch::Ref<'_, ProgramState> {
+        self.sender.borrow()
+    }
+
+    fn subscribe(&self) -> watch::Receiver<ProgramState> {
+        self.sender.subscribe()
+    }
+
+    fn update(&self, modify: impl FnOnce(&mut ProgramState)) {
+        // Keeps the update after those of a supervisor being aborted.
+        let _retired = self.retired.lock();
+        self.write(modify);
+    }
+
+    fn write(&self, modify: impl FnOnce(&mut ProgramState)) {
+        self.sender.send_modify(modify);
+        let result = encode_record(&*self.sender.borrow()).and_then(|bytes| Ok(self.states.insert(&self.id, bytes)?));
+        if let Err(err) = result {
+            warn!("failed to save the state of program {}: {}", self.id, err);
+        }
+    }
 }
 
 impl TryFrom<&mut InnerProgram> for Program {
     type Error = Error;
 
     fn try_from(inner: &mut InnerProgram) -> std::result::Result<Self, Self::Error> {
+        let state = inner.state.borrow();
         Ok(Self {
             id: inner.id.clone(),
             path: inner.meta.path.clone(),
             program_type: inner.meta.program_type,
-            status: inner.status,
+            status: state.status,
             addr: inner.meta.addr.clone().unwrap_or_default(),
+            fuel_consumed: inner.usage.fuel_consumed.load(Ordering::Relaxed),
+            restart_count: state.restart_count,
+            exit_code: state.exit_code,
         })
     }
 }
 
+impl From<&InnerProgram> for ProgramDetails {
+    fn from(inner: &InnerProgram) -> Self {
+        let meta = &inner.meta;
+        let state = inner.state.borrow();
+        let (volumes, dirs): (Vec<_>, Vec<_>) = meta.dirs.iter().partition(|dir| dir.volume.is_some());
+
+        Self {
+            id: inner.id.clone(),
+            path: meta.path.clone(),
+            program_type: meta.program_type,
+            status: state.status,
+            addr: meta.addr.clone().unwrap_or_default(),
+            args: meta.args.clone(),
+            env: meta.env.iter().cloned().collect(),
+            inherit_env: meta.inherit_env,
+            labels: meta.labels.iter().cloned().collect(),
+            http2_only: meta.http2_only,
+            tls_cert: path_string(&meta.tls_cert),
+            tls_key: path_string(&meta.tls_key),
+            max_connections: meta.max_connections.unwrap_or_default(),
+            max_concurrent_requests: meta.max_concurrent_requests.unwrap_or_default(),
+            request_backlog: meta.request_backlog,
+            routes: meta
+                .routes
+                .iter()
+                .map(|route| Route {
+                    host: route.host.clone().unwrap_or_default(),
+                    path: route.path.clone(),
+                })
+                .collect(),
+            rate_limit: Some(RateLimit {
+                rate: meta.rate_limit.unwrap_or_default(),
+                burst: meta.rate_limit_burst.unwrap_or_default(),
+                header: meta.rate_limit_header.clone().unwrap_or_default(),
+            }),
+            dirs: dirs
+                .into_iter()
+                .map(|dir| DirMount {
+                    host_path: dir.host_path.clone(),
+                    guest_path: dir.guest_path.clone(),
+                    read_only: dir.read_only,
+                })
+                .collect(),
+            volumes: volumes
+                .into_iter()
+                .map(|dir| VolumeMount {
+                    name: dir.volume.clone().unwrap_or_default(),
+                    guest_path: dir.guest_path.clone(),
+                    read_only: dir.read_only,
+                })
+                .collect(),
+            limits: Some(ResourceLimits {
+                memory: meta.memory_limit.unwrap_or_default(),
+                table_elements: meta.table_elements_limit.unwrap_or_default(),
+                instances: meta.instances_limit.unwrap_or_default(),
+                fuel: meta.fuel.unwrap_or_default(),
+            }),
+            timeout: meta.timeout.map(|timeout| timeout.as_secs()).unwrap_or_default(),
+            request_fuel: meta.request_fuel.unwrap_or_default(),
+            request_timeout: meta
+                .request_timeout
+                .map(|timeout| timeout.as_secs())
+                .unwrap_or_default(),
+            restart: Some(RestartPolicy {
+                policy: meta.restart_policy,
+                max_retries: meta.max_retries.unwrap_or_default(),
+            }),
+            restart_count: state.restart_count,
+            exit_code: state.exit_code,
+            error: state.error.clone().unwrap_or_default(),
+            fuel_consumed: inner.usage.fuel_consumed.load(Ordering::Relaxed),
+            created_at: unix_secs(Some(state.created_at)),
+            started_at: unix_secs(state.started_at),
+            finished_at: unix_secs(state.finished_at),
+            module_hash: inner.usage.module_hash.lock().clone().unwrap_or_default(),
+            engine_config: Default::default(),
+            log: Some(LogConfig {
+                max_size: meta.log_max_size.unwrap_or_default(),
+                max_files: meta.log_max_files.unwrap_or_default(),
+                driver: meta.log_driver.unwrap_or_default(),
+                syslog_address: meta.syslog_address.clone().unwrap_or_default(),
+            }),
+        }
+    }
+}
+
 impl Server {
-    pub async fn new<P: AsRef<Path>>(db: Db, logs_dir: P) -> Result<Self> {
+    pub async fn new<P: AsRef<Path>>(
+        db: Db,
+        logs_dir: P,
+        volumes_dir: P,
+        log_options: LogOptions,
+        ingress: Option<Arc<Ingress>>,
+    ) -> Result<Self> {
         let service = Self {
+            states: db.open_tree("states")?,
             db,
-            engines: new_engines()?,
+            ingress: ingress.is_some(),
+            engines: new_engines(ingress)?,
             programs: Arc::new(Mutex::new(HashMap::new())),
             logs_dir: logs_dir.as_ref().to_path_buf(),
+            volumes_dir: volumes_dir.as_ref().to_path_buf(),
+            log_options,
         };
         service.load_from_db().await?;
 
         Ok(service)
     }
 
+    /// Loads the programs from the db, and resumes those that were running
+    /// when the daemon stopped.
     async fn load_from_db(&self) -> Result<()> {
         for data in self.db.iter() {
             let (id, bytes) = data?;
-            self.run_inner(
-                std::str::from_utf8(id.to_vec().as_ref())?,
-                bincode::deserialize(&bytes)?,
-            )
-            .await?
+            // A record that can't be loaded shouldn't keep the daemon and the
+            // other programs from starting.
+            if let Err(err) = self.load_program(&id, &bytes).await {
+                warn!("failed to load program {}: {}", String::from_utf8_lossy(&id), err);
+            }
         }
         Ok(())
     }
 
-    async fn run_inner(&self, id: &str, meta: ProgramMeta) -> Result<()> {
+    async fn load_program(&self, id: &[u8], bytes: &[u8]) -> Result<()> {
+        let id = std::str::from_utf8(id)?;
+        let meta = ProgramMeta::decode(bytes)?;
+
+        match self.states.get(id)? {
+            Some(bytes) => match ProgramState::decode(&bytes) {
+                Ok(state) if should_resume(&meta, &state) => {
+                    self.run_inner(id, meta, state).await?;
+                }
+                Ok(state) => self.insert_inner(id, meta, state)?,
+                // The program is kept with the error, rather than dropped
+                // for a state that can't be decoded.
+                Err(err) => {
+                    let state = ProgramState {
+                        status: PROGRAM_STATUS_ERROR,
+                        error: Some(format!("failed to load the state: {:#}", err)),
+                        ..ProgramState::new()
+                    };
+                    self.insert_inner(id, meta, state)?;
+                }
+            },
+            // The program was created before states were persisted, it's run
+            // as the daemon used to run every program when it started, and
+            // its state is persisted from then on.
+            None => {
+                self.run_inner(id, meta, ProgramState::new()).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds a program that is not running.
+    fn insert_inner(&self, id: &str, meta: ProgramMeta, state: ProgramState) -> Result<()> {
+        let log = self.open_log(id, &meta)?;
+        self.programs.lock().insert(
+            id.to_string(),
+            InnerProgram {
+                id: id.to_string(),
+                meta,
+                handler: None,
+                state: Arc::new(StateHandle::new(id, state, self.states.clone())),
+                usage: Arc::new(ProgramUsage::default()),
+                log,
+                stdin: None,
+            },
+        );
+        Ok(())
+    }
+
+    /// Opens the log file of the program, the log options of the program take
+    /// precedence over the defaults of the daemon.
+    fn open_log(&self, id: &str, meta: &ProgramMeta) -> Result<Arc<LogWriter>> {
+        let options = LogOptions {
+            driver: meta.log_driver.or(self.log_options.driver),
+            rotation: LogRotation {
+                max_size: meta.log_max_size.or(self.log_options.rotation.max_size),
+                max_files: meta.log_max_files.or(self.log_options.rotation.max_files),
+            },
+            syslog_address: meta
+                .syslog_address
+                .clone()
+                .or_else(|| self.log_options.syslog_address.clone()),
+        };
+        Ok(Arc::new(LogWriter::new(id, open_driver(&self.logs_dir, id, &options)?)))
+    }
+
+    /// Runs the program, and returns a receiver of its output which is
+    /// subscribed before the program starts.
+    ///
+    /// A restarted program keeps its log writer, so the followers of its logs
+    /// go on receiving its output.
+    async fn run_inner(&self, id: &str, meta: ProgramMeta, state: ProgramState) -> Result<LogReceiver> {
+        let log = match self.programs.lock().get(id) {
+            Some(program) => program.log.clone(),
+            None => self.open_log(id, &meta)?,
+        };
+        let output = log.subscribe();
+        let (stdin_sender, stdin) = ProgramStdin::pipe(meta.tty);
+        let io = ProgramIo {
+            stdin,
+            stdout: ProgramOutput::stdout(log.clone(), meta.tty),
+            stderr: ProgramOutput::stderr(log.clone(), meta.tty),
+        };
+
         let mut programs = self.programs.lock();
-        let (sender, receiver) = oneshot::channel();
         let engine = self
             .engines
             .get(&meta.program_type)
             .ok_or(anyhow!("unknown program type {}", meta.program_type))?
             .clone();
 
-        let mut stdout = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(self.logs_dir.join(id))?;
-        let stdout_clone = stdout.try_clone()?;
+        let usage = Arc::new(ProgramUsage::default());
+        let state = Arc::new(StateHandle::new(id, state, self.states.clone()));
 
         let id = id.to_string();
         programs.insert(
@@ -106,35 +429,85 @@ impl Server {
             InnerProgram {
                 id: id.clone(),
                 meta: meta.clone(),
-                receiver,
-                handler: task::spawn(async move {
-                    match engine.run(meta, stdout_clone).await {
-                        Ok(_) => {}
-                        Err(e) => {
-                            error!("running program {} error: {}", id, e);
-                            if let Err(file_err) = stdout.write_fmt(format_args!("{}\n", e)) {
-                                warn!("write error log failed: {}", file_err);
-                            }
-                            if sender.send(e).is_err() {
-                                warn!("the receiver dropped");
-                            }
-                        }
-                    }
-                }),
-                status: PROGRAM_STATUS_RUNNING,
-                error: None,
+                stdin: meta.interactive.then_some(stdin_sender),
+                handler: Some(task::spawn(supervise(
+                    id,
+                    engine,
+                    meta,
+                    io,
+                    usage.clone(),
+                    state.clone(),
+                ))),
+                state,
+                usage,
+                log,
             },
         );
 
+        Ok(output)
+    }
+
+    /// Checks that an HTTP program can be reached, and that its routes are not
+    /// used by other programs.
+    fn check_routes(&self, meta: &ProgramMeta) -> Result<(), Status> {
+        if meta.addr.is_none() && meta.routes.is_empty() {
+            return Err(Status::invalid_argument("either an address or a route is required"));
+        }
+        if !meta.routes.is_empty() && !self.ingress {
+            return Err(Status::failed_precondition("the daemon has no ingress listener"));
+        }
+        let programs = self.programs.lock();
+        for route in &meta.routes {
+            if let Some(program) = programs.values().find(|program| program.meta.routes.contains(route)) {
+                return Err(Status::invalid_argument(format!(
+                    "the route {} is already used by {}",
+                    route, program.id
+                )));
+            }
+        }
         Ok(())
     }
 
-    async fn update_db_and_run(&self, id: &str, meta: ProgramMeta) -> Result<Response<ProgramResponse>, Status> {
-        match bincode::serialize(&meta) {
+    /// Creates the ID and the metadata of a new CLI program.
+    fn new_cli_program(&self, req: RunRequest) -> Result<(String, ProgramMeta), Status> {
+        let file_path = Path::new(&req.path);
+        let name = file_path.file_stem();
+        if name.is_none() {
+            return Err(Status::internal(format!(
+                "failed to get file name in path {}",
+                req.path
+            )));
+        }
+        let id = format!("{}-{}", name.unwrap().to_str().unwrap(), generate_random_string(7));
+
+        info!("Execute newly added program: {} ({})", id, req.path);
+
+        let mut meta = ProgramMeta {
+            path: req.path,
+            program_type: PROGRAM_TYPE_CLI,
+            addr: None,
+            args: req.args,
+            timeout: (req.timeout > 0).then(|| Duration::from_secs(req.timeout)),
+            dirs: [to_preopen_dirs(req.dirs)?, self.to_volume_dirs(req.volumes)?].concat(),
+            env: to_env(req.env)?,
+            inherit_env: req.inherit_env,
+            labels: to_labels(req.labels)?,
+            interactive: req.interactive,
+            tty: req.tty,
+            ..Default::default()
+        };
+        set_limits(&mut meta, req.limits);
+        set_log_config(&mut meta, req.log)?;
+        to_restart_policy(&mut meta, req.restart)?;
+
+        Ok((id, meta))
+    }
+
+    async fn update_db_and_run(&self, id: &str, meta: ProgramMeta) -> Result<LogReceiver, Status> {
+        match encode_record(&meta) {
             Ok(bytes) => {
                 self.db.insert(id, bytes).map_err(to_status)?;
-                self.run_inner(id, meta).await.map_err(to_status)?;
-                Ok(Response::new(ProgramResponse { id: id.to_string() }))
+                self.run_inner(id, meta, ProgramState::new()).await.map_err(to_status)
             }
             Err(err) => Err(Status::internal(err.to_string())),
         }
@@ -144,6 +517,125 @@ impl Server {
         let programs = self.programs.lock();
         programs.par_iter().map(|(key, _)| key.clone()).collect()
     }
+
+    /// Returns the IDs of the programs a log request selects, the programs
+    /// requested by ID come first.
+    fn log_programs(&self, req: &LogRequest) -> Result<Vec<String>, Status> {
+        let keys = self.get_program_keys();
+        let mut ids = vec![];
+        for id in (!req.id.is_empty()).then_some(&req.id).into_iter().chain(&req.ids) {
+            let id = search_id(keys.as_ref(), id).map_err(to_status)?;
+            if !ids.contains(&id) {
+                ids.push(id);
+            }
+        }
+        if !req.labels.is_empty() {
+            let programs = self.programs.lock();
+            let mut labeled: Vec<String> = programs
+                .values()
+                .filter(|program| {
+                    req.labels
+                        .iter()
+                        .all(|label| program.meta.labels.iter().any(|(key, value)| label == (key, value)))
+                })
+                .map(|program| program.id.clone())
+                .collect();
+            if labeled.is_empty() {
+                return Err(Status::not_found("no program matches the labels"));
+            }
+            labeled.sort();
+            for id in labeled {
+                if !ids.contains(&id) {
+                    ids.push(id);
+                }
+            }
+        }
+        if ids.is_empty() {
+            return Err(Status::invalid_argument("no program is specified"));
+        }
+        Ok(ids)
+    }
+
+    /// Returns the IDs of the programs using the volume.
+    fn volume_programs(&self, name: &str) -> Vec<String> {
+        let programs = self.programs.lock();
+        let mut ids: Vec<String> = programs
+            .values()
+            .filter(|program| program.meta.dirs.iter().any(|dir| dir.volume.as_deref() == Some(name)))
+            .map(|program| program.id.clone())
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    fn get_volume(&self, name: &str) -> Result<Volume, Status> {
+        check_volume_name(name)?;
+        let path = self.volumes_dir.join(name);
+        let metadata = match path.metadata() {
+            Ok(metadata) if metadata.is_dir() => metadata,
+            _ => return Err(Status::not_found(format!("volume {} not found", name))),
+        };
+        let created_at = metadata
+            .created()
+            .or_else(|_| metadata.modified())
+            .map_err(to_status)?
+            .duration_since(UNIX_EPOCH)
+            .map_err(to_status)?;
+
+        Ok(Volume {
+            name: name.to_string(),
+            path: absolute(path)?.to_string_lossy().to_string(),
+            created_at: created_at.as_secs(),
+            programs: self.volume_programs(name),
+        })
+    }
+
+    fn to_volume_dirs(&self, volumes: Vec<VolumeMount>) -> Result<Vec<PreopenDir>, Status> {
+        volumes
+            .into_iter()
+            .map(|volume| {
+                let path = self.get_volume(&volume.name)?.path;
+                if volume.guest_path.is_empty() {
+                    return Err(Status::invalid_argument(format!(
+                        "missing the guest path of volume {}",
+                        volume.name
+                    )));
+                }
+                Ok(PreopenDir {
+                    host_path: path,
+                    guest_path: volume.guest_path,
+                    read_only: volume.read_only,
+                    volume: Some(volume.name),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Volume names are used as directory names, so only allow a safe subset of
+/// characters.
+fn check_volume_name(name: &str) -> Result<(), Status> {
+    let valid = name
+        .chars()
+        .enumerate()
+        .all(|(i, c)| c.is_ascii_alphanumeric() || (i > 0 && matches!(c, '_' | '.' | '-')));
+    if name.is_empty() || !valid {
+        return Err(Status::invalid_argument(format!(
+            "invalid volume name {:?}, only [a-zA-Z0-9][a-zA-Z0-9_.-]* are allowed",
+            name
+        )));
+    }
+    Ok(())
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        // Stop the programs without updating their states, so that they will
+        // be resumed when the daemon starts again.
+        for program in self.programs.lock().values() {
+            program.abort();
+        }
+    }
 }
 
 fn search_id(keys: &Vec<String>, id: &str) -> Result<String> {
@@ -158,37 +650,257 @@ fn search_id(keys: &Vec<String>, id: &str) -> Result<String> {
     }
 }
 
-fn to_status<E: Display>(err: E) -> Status {
-    Status::internal(err.to_string())
+fn set_limits(meta: &mut ProgramMeta, limits: Option<ResourceLimits>) {
+    let limits = limits.unwrap_or_default();
+    meta.memory_limit = (limits.memory > 0).then_some(limits.memory);
+    meta.table_elements_limit = (limits.table_elements > 0).then_some(limits.table_elements);
+    meta.instances_limit = (limits.instances > 0).then_some(limits.instances);
+    meta.fuel = (limits.fuel > 0).then_some(limits.fuel);
 }
 
-#[async_trait]
-impl Wacker for Server {
-    async fn run(&self, request: Request<RunRequest>) -> Result<Response<ProgramResponse>, Status> {
-        let req = request.into_inner();
+fn set_log_config(meta: &mut ProgramMeta, log: Option<LogConfig>) -> Result<(), Status> {
+    let log = log.unwrap_or_default();
+    if log.driver > LOG_DRIVER_SYSLOG {
+        return Err(Status::invalid_argument(format!("unknown log driver {}", log.driver)));
+    }
+    meta.log_max_size = (log.max_size > 0).then_some(log.max_size);
+    meta.log_max_files = (log.max_files > 0).then_some(log.max_files);
+    meta.log_driver = (log.driver > 0).then_some(log.driver);
+    meta.syslog_address = (!log.syslog_address.is_empty()).then_some(log.syslog_address);
+    Ok(())
+}
 
-        let file_path = Path::new(&req.path);
-        let name = file_path.file_stem();
-        if name.is_none() {
-            return Err(Status::internal(format!(
-                "failed to get file name in path {}",
-                req.path
-            )));
+fn set_rate_limit(meta: &mut ProgramMeta, rate_limit: Option<RateLimit>) -> Result<(), Status> {
+    let rate_limit = rate_limit.unwrap_or_default();
+    if rate_limit.rate == 0 {
+        if rate_limit.burst > 0 || !rate_limit.header.is_empty() {
+            return Err(Status::invalid_argument("the rate limit is not set"));
         }
-        let id = format!("{}-{}", name.unwrap().to_str().unwrap(), generate_random_string(7));
+        return Ok(());
+    }
+    if !rate_limit.header.is_empty() && HeaderName::from_bytes(rate_limit.header.as_bytes()).is_err() {
+        return Err(Status::invalid_argument(format!(
+            "invalid header name {}",
+            rate_limit.header
+        )));
+    }
+    meta.rate_limit = Some(rate_limit.rate);
+    meta.rate_limit_burst = (rate_limit.burst > 0).then_some(rate_limit.burst);
+    meta.rate_limit_header = (!rate_limit.header.is_empty()).then_some(rate_limit.header);
+    Ok(())
+}
 
-        info!("Execute newly added program: {} ({})", id, req.path);
+fn to_tls_path(path: String) -> Result<Option<PathBuf>, Status> {
+    if path.is_empty() {
+        return Ok(None);
+    }
+    if !Path::new(&path).is_file() {
+        return Err(Status::invalid_argument(format!("{} is not a file", path)));
+    }
+    Ok(Some(PathBuf::from(path)))
+}
+
+fn path_string(path: &Option<PathBuf>) -> String {
+    path.as_ref()
+        .map(|path| path.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+fn to_preopen_dirs(dirs: Vec<DirMount>) -> Result<Vec<PreopenDir>, Status> {
+    dirs.into_iter()
+        .map(|dir| {
+            if !Path::new(&dir.host_path).is_dir() {
+                return Err(Status::invalid_argument(format!(
+                    "{} is not a directory",
+                    dir.host_path
+                )));
+            }
+            if dir.guest_path.is_empty() {
+                return Err(Status::invalid_argument(format!(
+                    "missing the guest path of {}",
+                    dir.host_path
+                )));
+            }
+            Ok(PreopenDir {
+                host_path: dir.host_path,
+                guest_path: dir.guest_path,
+                read_only: dir.read_only,
+                volume: None,
+            })
+        })
+        .collect()
+}
+
+fn to_env(env: std::collections::HashMap<String, String>) -> Result<Vec<(String, String)>, Status> {
+    let mut env: Vec<(String, String)> = env.into_iter().collect();
+    if let Some((key, _)) = env.iter().find(|(key, _)| key.is_empty() || key.contains('=')) {
+        return Err(Status::invalid_argument(format!(
+            "invalid environment variable name {:?}",
+            key
+        )));
+    }
+    env.sort();
+    Ok(env)
+}
 
-        self.update_db_and_run(
-            id.as_str(),
-            ProgramMeta {
-                path: req.path,
-                program_type: PROGRAM_TYPE_CLI,
-                addr: None,
-                args: req.args,
+fn to_routes(routes: Vec<Route>) -> Result<Vec<RouteRule>, Status> {
+    let mut rules: Vec<RouteRule> = vec![];
+    for route in routes {
+        if !route.path.starts_with('/') {
+            return Err(Status::invalid_argument(format!(
+                "the path of a route must start with /: {}",
+                route.path
+            )));
+        }
+        let host = match route.host.is_empty() {
+            true => None,
+            false => match route.host.parse::<Authority>() {
+                Ok(authority) if authority.port().is_none() => Some(authority.host().to_ascii_lowercase()),
+                _ => return Err(Status::invalid_argument(format!("invalid route host {}", route.host))),
             },
-        )
-        .await
+        };
+        let rule = RouteRule { host, path: route.path };
+        if rules.contains(&rule) {
+            return Err(Status::invalid_argument(format!("duplicate route {}", rule)));
+        }
+        rules.push(rule);
+    }
+    Ok(rules)
+}
+
+fn to_labels(labels: std::collections::HashMap<String, String>) -> Result<Vec<(String, String)>, Status> {
+    let mut labels: Vec<(String, String)> = labels.into_iter().collect();
+    if let Some((key, _)) = labels.iter().find(|(key, _)| key.is_empty() || key.contains('=')) {
+        return Err(Status::invalid_argument(format!("invalid label name {:?}", key)));
+    }
+    labels.sort();
+    Ok(labels)
+}
+
+/// Runs the program, and restarts it according to its restart policy.
+///
+/// The delay between restarts starts at one second and doubles after every
+/// restart up to a minute, and is reset once the program has been running
+/// for a while.
+async fn supervise(
+    id: String,
+    engine: Arc<dyn Engine>,
+    meta: ProgramMeta,
+    io: ProgramIo,
+    usage: Arc<ProgramUsage>,
+    state: Arc<StateHandle>,
+) {
+    let mut delay = RESTART_DELAY;
+    loop {
+        let started = Instant::now();
+        state.update_running(|state| {
+            state.status = PROGRAM_STATUS_RUNNING;
+            state.exit_code = None;
+            state.started_at = Some(SystemTime::now());
+            state.finished_at = None;
+        });
+
+        let result = engine.run(&id, meta.clone(), io.clone(), usage.clone()).await;
+
+        let (status, exit_code, error) = match result {
+            Ok(0) => (PROGRAM_STATUS_FINISHED, Some(0), None),
+            Ok(code) => (PROGRAM_STATUS_ERROR, Some(code), None),
+            Err(e) => {
+                error!("running program {} error: {:#}", id, e);
+                if let Err(file_err) = io.stderr.clone().write_fmt(format_args!("{:#}\n", e)) {
+                    warn!("write error log failed: {}", file_err);
+                }
+                (error_status(&e), None, Some(format!("{:#}", e)))
+            }
+        };
+
+        let restart = should_restart(&meta, status, state.borrow().restart_count);
+        state.update_running(|state| {
+            state.status = match restart {
+                true => PROGRAM_STATUS_RESTARTING,
+                false => status,
+            };
+            state.exit_code = exit_code;
+            state.error = error;
+            state.finished_at = Some(SystemTime::now());
+        });
+        if !restart {
+            return;
+        }
+
+        if started.elapsed() >= RESTART_DELAY_RESET {
+            delay = RESTART_DELAY;
+        }
+        info!("Restart the program {} in {}s", id, delay.as_secs());
+        time::sleep(delay).await;
+        delay = (delay * 2).min(MAX_RESTART_DELAY);
+
+        state.update_running(|state| state.restart_count += 1);
+    }
+}
+
+/// Whether to resume the program when the daemon starts, programs stopped by
+/// the user are only resumed under the always policy.
+fn should_resume(meta: &ProgramMeta, state: &ProgramState) -> bool {
+    match state.status {
+        PROGRAM_STATUS_RUNNING | PROGRAM_STATUS_RESTARTING => true,
+        PROGRAM_STATUS_STOPPED => meta.restart_policy == RESTART_POLICY_ALWAYS,
+        _ => false,
+    }
+}
+
+fn should_restart(meta: &ProgramMeta, status: u32, restart_count: u32) -> bool {
+    match meta.restart_policy {
+        RESTART_POLICY_ON_FAILURE => {
+            status != PROGRAM_STATUS_FINISHED && meta.max_retries.map_or(true, |max| restart_count < max)
+        }
+        RESTART_POLICY_ALWAYS | RESTART_POLICY_UNLESS_STOPPED => true,
+        _ => false,
+    }
+}
+
+fn to_restart_policy(meta: &mut ProgramMeta, restart: Option<RestartPolicy>) -> Result<(), Status> {
+    let restart = restart.unwrap_or_default();
+    if restart.policy > RESTART_POLICY_UNLESS_STOPPED {
+        return Err(Status::invalid_argument(format!(
+            "unknown restart policy {}",
+            restart.policy
+        )));
+    }
+    if restart.max_retries > 0 && restart.policy != RESTART_POLICY_ON_FAILURE {
+        return Err(Status::invalid_argument(
+            "max retries can only be used with the on-failure restart policy",
+        ));
+    }
+    meta.restart_policy = restart.policy;
+    meta.max_retries = (restart.max_retries > 0).then_some(restart.max_retries);
+    Ok(())
+}
+
+fn error_status(err: &Error) -> u32 {
+    match err.downcast_ref::<Trap>() {
+        Some(Trap::OutOfFuel) => PROGRAM_STATUS_FUEL_EXHAUSTED,
+        Some(Trap::Interrupt) => PROGRAM_STATUS_TIMED_OUT,
+        _ => PROGRAM_STATUS_ERROR,
+    }
+}
+
+fn unix_secs(time: Option<SystemTime>) -> u64 {
+    time.and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+fn to_status<E: Display>(err: E) -> Status {
+    Status::internal(err.to_string())
+}
+
+#[async_trait]
+impl Wacker for Server {
+    async fn run(&self, request: Request<RunRequest>) -> Result<Response<ProgramResponse>, Status> {
+        let (id, meta) = self.new_cli_program(request.into_inner())?;
+        self.update_db_and_run(id.as_str(), meta).await?;
+        Ok(Response::new(ProgramResponse { id }))
     }
 
     async fn serve(&self, request: Request<ServeRequest>) -> Result<Response<ProgramResponse>, Status> {
@@ -206,16 +918,44 @@ impl Wacker for Server {
 
         info!("Serve newly added program: {} ({})", id, req.path);
 
-        self.update_db_and_run(
-            id.as_str(),
-            ProgramMeta {
-                path: req.path,
-                program_type: PROGRAM_TYPE_HTTP,
-                addr: Option::from(req.addr),
-                args: vec![],
-            },
-        )
-        .await
+        let mut meta = ProgramMeta {
+            path: req.path,
+            program_type: PROGRAM_TYPE_HTTP,
+            addr: (!req.addr.is_empty()).then_some(req.addr),
+            args: vec![],
+            request_fuel: (req.request_fuel > 0).then_some(req.request_fuel),
+            request_timeout: (req.request_timeout > 0).then(|| Duration::from_secs(req.request_timeout)),
+            http2_only: req.http2_only,
+            tls_cert: to_tls_path(req.tls_cert)?,
+            tls_key: to_tls_path(req.tls_key)?,
+            max_connections: (req.max_connections > 0).then_some(req.max_connections),
+            max_concurrent_requests: (req.max_concurrent_requests > 0).then_some(req.max_concurrent_requests),
+            request_backlog: req.request_backlog,
+            dirs: [to_preopen_dirs(req.dirs)?, self.to_volume_dirs(req.volumes)?].concat(),
+            env: to_env(req.env)?,
+            inherit_env: req.inherit_env,
+            labels: to_labels(req.labels)?,
+            routes: to_routes(req.routes)?,
+            ..Default::default()
+        };
+        self.check_routes(&meta)?;
+        if meta.tls_cert.is_some() != meta.tls_key.is_some() {
+            return Err(Status::invalid_argument(
+                "the TLS certificate and private key must be set together",
+            ));
+        }
+        if meta.request_backlog > 0 && meta.max_concurrent_requests.is_none() {
+            return Err(Status::invalid_argument(
+                "the request backlog requires a maximum number of concurrent requests",
+            ));
+        }
+        set_limits(&mut meta, req.limits);
+        set_log_config(&mut meta, req.log)?;
+        set_rate_limit(&mut meta, req.rate_limit)?;
+        to_restart_policy(&mut meta, req.restart)?;
+
+        self.update_db_and_run(id.as_str(), meta).await?;
+        Ok(Response::new(ProgramResponse { id }))
     }
 
     async fn list(&self, _: Request<()>) -> Result<Response<ListResponse>, Status> {
@@ -223,19 +963,6 @@ impl Wacker for Server {
         let mut programs = self.programs.lock();
 
         for (_, inner) in programs.iter_mut() {
-            match inner.status {
-                PROGRAM_STATUS_RUNNING if inner.handler.is_finished() => {
-                    inner.status = match inner.receiver.try_recv() {
-                        Ok(err) => {
-                            inner.error = Option::from(err);
-                            PROGRAM_STATUS_ERROR
-                        }
-                        Err(TryRecvError::Empty) | Err(TryRecvError::Closed) => PROGRAM_STATUS_FINISHED,
-                    };
-                }
-                _ => {}
-            };
-
             reply.programs.push(inner.try_into().map_err(to_status)?);
         }
 
@@ -253,9 +980,12 @@ impl Wacker for Server {
             info!("Stop the program: {}", id);
 
             let program = programs.get_mut(id.as_str()).unwrap();
-            if !program.handler.is_finished() {
-                program.handler.abort();
-                program.status = PROGRAM_STATUS_STOPPED;
+            if program.is_running() {
+                program.abort();
+                program.state.update(|state| {
+                    state.status = PROGRAM_STATUS_STOPPED;
+                    state.finished_at = Some(SystemTime::now());
+                });
             }
         }
         Ok(Response::new(()))
@@ -270,16 +1000,18 @@ impl Wacker for Server {
 
             info!("Restart the program: {}", id);
 
-            let meta = {
+            let (meta, state) = {
                 let programs = self.programs.lock();
                 let program = programs.get(id.as_str()).unwrap();
-                if !program.handler.is_finished() {
-                    program.handler.abort();
-                }
-                program.meta.clone()
+                program.abort();
+                let state = ProgramState {
+                    created_at: program.state.borrow().created_at,
+                    ..ProgramState::new()
+                };
+                (program.meta.clone(), state)
             };
 
-            self.run_inner(id.as_str(), meta).await.map_err(to_status)?;
+            self.run_inner(id.as_str(), meta, state).await.map_err(to_status)?;
         }
         Ok(Response::new(()))
     }
@@ -294,22 +1026,18 @@ impl Wacker for Server {
 
             info!("Delete the program: {}", id);
 
-            let program = programs.get(id.as_str()).unwrap();
-            if !program.handler.is_finished() {
-                program.handler.abort();
-            }
+            programs.get(id.as_str()).unwrap().abort();
 
-            if let Err(err) = remove_file(self.logs_dir.join(id.as_str())) {
-                if err.kind() != ErrorKind::NotFound {
-                    return Err(Status::internal(format!(
-                        "failed to remove the log file for {}: {}",
-                        id.as_str(),
-                        err
-                    )));
-                }
+            if let Err(err) = remove_logs(&self.logs_dir, id.as_str()) {
+                return Err(Status::internal(format!(
+                    "failed to remove the log file for {}: {}",
+                    id.as_str(),
+                    err
+                )));
             }
 
             self.db.remove(id.as_str()).map_err(to_status)?;
+            self.states.remove(id.as_str()).map_err(to_status)?;
             programs.remove(id.as_str());
         }
         Ok(Response::new(()))
@@ -318,70 +1046,633 @@ impl Wacker for Server {
     type LogsStream = Pin<Box<dyn Stream<Item = Result<LogResponse, Status>> + Send>>;
 
     async fn logs(&self, request: Request<LogRequest>) -> Result<Response<Self::LogsStream>, Status> {
+        let req = request.into_inner();
+        if req.stream > LOG_STREAM_STDERR {
+            return Err(Status::invalid_argument(format!("unknown log stream {}", req.stream)));
+        }
+        let ids = self.log_programs(&req)?;
+
+        let filter = LogFilter::new(&req);
+        let grep = LogGrep::new(&req)?;
+        let mut records = vec![];
+        let mut receivers = vec![];
+        let mut greps = vec![];
+        for (index, id) in ids.iter().enumerate() {
+            let LogSnapshot { files, receiver } = {
+                let programs = self.programs.lock();
+                // The program may have been deleted since its ID was resolved.
+                programs
+                    .get(id.as_str())
+                    .ok_or_else(|| Status::not_found(format!("program {} not found", id)))?
+                    .log
+                    .snapshot()?
+            };
+            let Some(files) = files else {
+                return Err(Status::failed_precondition(format!(
+                    "the log driver of program {} doesn't support reading logs",
+                    id
+                )));
+            };
+            let mut program_records = read_records(files, &filter).await.map_err(to_status)?;
+            if let Some(mut grep) = grep.clone() {
+                program_records = program_records
+                    .into_iter()
+                    .flat_map(|record| grep.lines(record))
+                    .collect();
+                // The last line may be completed later when following.
+                if !req.follow {
+                    program_records.extend(grep.flush());
+                }
+                greps.push(grep);
+            }
+            records.extend(
+                tail(program_records, req.tail as usize)
+                    .into_iter()
+                    .map(|record| (index, record)),
+            );
+            receivers.push(receiver);
+        }
+        // Merge the logs of the programs by time, the sort is stable so the
+        // records of a program keep their order.
+        if ids.len() > 1 {
+            records.sort_by_key(|(_, record)| record.time);
+        }
+        let mut formatter = LogFormatter::new(ids, req.timestamps);
+
+        let (tx, rx) = mpsc::channel(128);
+        tokio::spawn(async move {
+            for response in formatter.format(records) {
+                if tx.send(Result::<_, Status>::Ok(response)).await.is_err() {
+                    return;
+                }
+            }
+
+            if req.follow {
+                follow(receivers, filter, greps, formatter, tx).await;
+            }
+        });
+
+        let output_stream = ReceiverStream::new(rx);
+        Ok(Response::new(Box::pin(output_stream) as Self::LogsStream))
+    }
+
+    async fn wait(&self, request: Request<WaitRequest>) -> Result<Response<WaitResponse>, Status> {
         let req = request.into_inner();
         let keys = self.get_program_keys();
         let id = search_id(keys.as_ref(), req.id.as_str()).map_err(to_status)?;
 
-        let mut file = File::open(self.logs_dir.join(id)).await?;
-        let mut contents = String::new();
-        let last_position = file.read_to_string(&mut contents).await?;
-        let lines: Vec<&str> = contents.split_inclusive('\n').collect();
+        let mut receiver = {
+            let programs = self.programs.lock();
+            // The program may have been deleted since its ID was resolved.
+            programs
+                .get(id.as_str())
+                .ok_or_else(|| Status::not_found(format!("program {} not found", id)))?
+                .state
+                .subscribe()
+        };
+        let state = receiver
+            .wait_for(|state| !matches!(state.status, PROGRAM_STATUS_RUNNING | PROGRAM_STATUS_RESTARTING))
+            .await
+            .map_err(|_| Status::aborted(format!("program {} was restarted or deleted", id)))?;
+
+        Ok(Response::new(WaitResponse {
+            status: state.status,
+            exit_code: state.exit_code,
+        }))
+    }
+
+    async fn inspect(&self, request: Request<InspectRequest>) -> Result<Response<InspectResponse>, Status> {
+        let req = request.into_inner();
+        let keys = self.get_program_keys();
+        let programs = self.programs.lock();
+
+        let mut reply = InspectResponse { programs: vec![] };
+        for id in req.ids {
+            let id = search_id(keys.as_ref(), id.as_str()).map_err(to_status)?;
+            // The program may have been deleted since the IDs were listed.
+            let program = programs
+                .get(id.as_str())
+                .ok_or_else(|| Status::not_found(format!("program {} not found", id)))?;
+            let engine_config = match self.engines.get(&program.meta.program_type) {
+                Some(engine) => engine.config().into_iter().collect(),
+                None => Default::default(),
+            };
+            reply.programs.push(ProgramDetails {
+                engine_config,
+                ..program.into()
+            });
+        }
+
+        Ok(Response::new(reply))
+    }
+
+    async fn create_volume(&self, request: Request<CreateVolumeRequest>) -> Result<Response<Volume>, Status> {
+        let req = request.into_inner();
+        check_volume_name(&req.name)?;
+
+        info!("Create the volume: {}", req.name);
+
+        if let Err(err) = create_dir(self.volumes_dir.join(&req.name)) {
+            return match err.kind() {
+                ErrorKind::AlreadyExists => Err(Status::already_exists(format!("volume {} already exists", req.name))),
+                _ => Err(to_status(err)),
+            };
+        }
+        Ok(Response::new(self.get_volume(&req.name)?))
+    }
 
-        let len = lines.len();
-        let mut tail = req.tail as usize;
-        if tail == 0 || tail > len {
-            tail = len;
+    async fn list_volumes(&self, _: Request<()>) -> Result<Response<ListVolumesResponse>, Status> {
+        let mut volumes = vec![];
+        for entry in read_dir(&self.volumes_dir)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            // Directories that weren't created as volumes are skipped rather
+            // than failing the listing.
+            let file_name = entry.file_name();
+            let Some(name) = file_name.to_str() else {
+                warn!("skip the volume directory {:?}: invalid name", file_name);
+                continue;
+            };
+            if let Err(err) = check_volume_name(name) {
+                warn!("skip the volume directory {:?}: {}", name, err.message());
+                continue;
+            }
+            volumes.push(self.get_volume(name)?);
+        }
+        volumes.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(Response::new(ListVolumesResponse { volumes }))
+    }
+
+    async fn remove_volume(&self, request: Request<RemoveVolumeRequest>) -> Result<Response<()>, Status> {
+        let req = request.into_inner();
+
+        for name in req.names {
+            let volume = self.get_volume(&name)?;
+            if !volume.programs.is_empty() {
+                return Err(Status::failed_precondition(format!(
+                    "volume {} is in use by {}",
+                    name,
+                    volume.programs.join(", ")
+                )));
+            }
+
+            info!("Remove the volume: {}", name);
+
+            remove_dir_all(self.volumes_dir.join(&name))?;
         }
-        let content = &lines[len - tail..];
+        Ok(Response::new(()))
+    }
+
+    async fn inspect_volume(&self, request: Request<InspectVolumeRequest>) -> Result<Response<Volume>, Status> {
+        let req = request.into_inner();
+        Ok(Response::new(self.get_volume(&req.name)?))
+    }
+
+    type AttachStream = Pin<Box<dyn Stream<Item = Result<AttachResponse, Status>> + Send>>;
+
+    async fn attach(&self, request: Request<Streaming<AttachRequest>>) -> Result<Response<Self::AttachStream>, Status> {
+        let mut requests = request.into_inner();
+        let (id, output, stdin, stdin_once) = match requests.message().await? {
+            Some(AttachRequest {
+                request: Some(attach_request::Request::Run(req)),
+            }) => {
+                let (id, meta) = self.new_cli_program(req)?;
+                let output = self.update_db_and_run(id.as_str(), meta).await?;
+                let stdin = self
+                    .programs
+                    .lock()
+                    .get(id.as_str())
+                    .and_then(|program| program.stdin.clone());
+                (id, output, stdin, true)
+            }
+            Some(AttachRequest {
+                request: Some(attach_request::Request::Program(req)),
+            }) => {
+                let keys = self.get_program_keys();
+                let id = search_id(keys.as_ref(), req.id.as_str()).map_err(to_status)?;
+                let programs = self.programs.lock();
+                // The program may have been deleted since its ID was resolved.
+                let program = programs
+                    .get(id.as_str())
+                    .ok_or_else(|| Status::not_found(format!("program {} not found", id)))?;
+                if req.stdin && program.stdin.is_none() {
+                    return Err(Status::failed_precondition(format!(
+                        "the stdin of program {} is not open, run it with --interactive",
+                        id
+                    )));
+                }
+                let stdin = req.stdin.then(|| program.stdin.clone()).flatten();
+                (id, program.log.subscribe(), stdin, false)
+            }
+            _ => {
+                return Err(Status::invalid_argument(
+                    "the first attach request must run a program or attach to one",
+                ))
+            }
+        };
+        let state = match self.programs.lock().get(id.as_str()) {
+            Some(program) => program.state.subscribe(),
+            None => return Err(Status::not_found(format!("program {} not found", id))),
+        };
+
+        // The client that runs the program closes the stdin of the program
+        // once it closes its stdin, while other clients can come and go.
+        let programs = self.programs.clone();
+        let program_id = id.clone();
+        tokio::spawn(async move {
+            if forward_stdin(requests, &stdin).await && stdin_once {
+                if let Some(program) = programs.lock().get_mut(program_id.as_str()) {
+                    if program
+                        .stdin
+                        .as_ref()
+                        .zip(stdin.as_ref())
+                        .is_some_and(|(a, b)| a.same_channel(b))
+                    {
+                        program.stdin = None;
+                    }
+                }
+            }
+        });
 
         let (tx, rx) = mpsc::channel(128);
-        tx.send(Result::<_, Status>::Ok(LogResponse {
-            content: content.concat(),
+        tx.send(Ok(AttachResponse {
+            response: Some(attach_response::Response::Id(id)),
         }))
         .await
         .map_err(to_status)?;
+        tokio::spawn(forward_output(tx, output, state));
 
-        if req.follow {
-            let mut stream = Box::pin(loop_stream(file, last_position));
-            tokio::spawn(async move {
-                while let Some(content) = stream.next().await {
-                    match tx
-                        .send(Result::<_, Status>::Ok(LogResponse {
-                            content: content.unwrap(),
-                        }))
-                        .await
-                    {
-                        Ok(_) => {
-                            // item (server response) was queued to be send to client
-                        }
-                        Err(_) => {
-                            // output_stream was build from rx and both are dropped
-                            break;
-                        }
+        let output_stream = ReceiverStream::new(rx);
+        Ok(Response::new(Box::pin(output_stream) as Self::AttachStream))
+    }
+
+    async fn list_routes(&self, _: Request<()>) -> Result<Response<ListRoutesResponse>, Status> {
+        let programs = self.programs.lock();
+        let mut routes = vec![];
+        for program in programs.values() {
+            let status = program.state.borrow().status;
+            routes.extend(program.meta.routes.iter().map(|route| IngressRoute {
+                host: route.host.clone().unwrap_or_default(),
+                path: route.path.clone(),
+                id: program.id.clone(),
+                status,
+            }));
+        }
+        routes.sort_by(|a, b| (&a.host, &a.path).cmp(&(&b.host, &b.path)));
+
+        Ok(Response::new(ListRoutesResponse { routes }))
+    }
+}
+
+/// Writes the stdin sent by an attached client to the program, and returns
+/// whether the client closed its stdin rather than going away.
+async fn forward_stdin(mut requests: Streaming<AttachRequest>, stdin: &Option<mpsc::Sender<Bytes>>) -> bool {
+    loop {
+        match requests.message().await {
+            Ok(Some(req)) => {
+                if let (Some(attach_request::Request::Stdin(bytes)), Some(stdin)) = (req.request, stdin) {
+                    if stdin.send(bytes.into()).await.is_err() {
+                        return false;
                     }
                 }
-            });
+            }
+            Ok(None) => return true,
+            Err(_) => return false,
         }
+    }
+}
 
-        let output_stream = ReceiverStream::new(rx);
-        Ok(Response::new(Box::pin(output_stream) as Self::LogsStream))
+/// Sends the output of the program to an attached client until the program
+/// exits or the client goes away.
+async fn forward_output(
+    tx: mpsc::Sender<Result<AttachResponse, Status>>,
+    mut output: LogReceiver,
+    mut state: watch::Receiver<ProgramState>,
+) {
+    // A client that is too slow to keep up with the output catches up from
+    // the log files, or is told how many records it missed if the log driver
+    // can't read them back.
+    let to_responses = |event: LogEvent| {
+        let records = match event {
+            LogEvent::Records(records) => records,
+            LogEvent::Dropped(dropped) => vec![dropped_notice(dropped)],
+        };
+        records.into_iter().map(|record| {
+            let bytes = record.bytes.to_vec();
+            Ok(AttachResponse {
+                response: Some(match record.stream {
+                    LOG_STREAM_STDERR => attach_response::Response::Stderr(bytes),
+                    _ => attach_response::Response::Stdout(bytes),
+                }),
+            })
+        })
+    };
+
+    let exited = |state: &ProgramState| !matches!(state.status, PROGRAM_STATUS_RUNNING | PROGRAM_STATUS_RESTARTING);
+    loop {
+        // Prefer the output, so that nothing written before the program
+        // exits is lost.
+        let event = tokio::select! {
+            biased;
+            event = output.recv() => event,
+            _ = state.wait_for(exited) => break,
+        };
+        let Some(event) = event else {
+            break;
+        };
+        for response in to_responses(event) {
+            if tx.send(response).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    while let Some(event) = output.try_recv().await {
+        for response in to_responses(event) {
+            if tx.send(response).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    // The state is gone if the program was restarted or deleted.
+    let response = match state.wait_for(exited).await {
+        Ok(state) => Ok(AttachResponse {
+            response: Some(attach_response::Response::Exit(WaitResponse {
+                status: state.status,
+                exit_code: state.exit_code,
+            })),
+        }),
+        Err(_) => Err(Status::aborted("the program was restarted or deleted")),
+    };
+    let _ = tx.send(response).await;
+}
+
+/// Reads the records of the log files that pass the filter.
+async fn read_records(files: LogFiles, filter: &LogFilter) -> Result<Vec<LogRecord>> {
+    let records = files.read().await?;
+    Ok(records.into_iter().filter(|record| filter.matches(record)).collect())
+}
+
+/// Sends the output of the programs as it's written, until the client goes
+/// away, all the programs are deleted or the end of the time range passes.
+///
+/// `greps` holds the grep of each program, it's empty if the lines are not
+/// filtered by grep.
+async fn follow(
+    receivers: Vec<LogReceiver>,
+    filter: LogFilter,
+    mut greps: Vec<LogGrep>,
+    mut formatter: LogFormatter,
+    tx: mpsc::Sender<Result<LogResponse, Status>>,
+) {
+    // A slow follower holds up the task of each receiver, which catches up
+    // from the log files once it lags behind.
+    let (events_tx, mut events) = mpsc::channel(1);
+    for (index, mut receiver) in receivers.into_iter().enumerate() {
+        let events_tx = events_tx.clone();
+        tokio::spawn(async move {
+            loop {
+                let event = tokio::select! {
+                    event = receiver.recv() => event,
+                    _ = events_tx.closed() => return,
+                };
+                let Some(event) = event else {
+                    return;
+                };
+                if events_tx.send((index, event)).await.is_err() {
+                    return;
+                }
+            }
+        });
+    }
+    drop(events_tx);
+    let until = async {
+        match filter.until {
+            Some(until) => time::sleep(until.duration_since(SystemTime::now()).unwrap_or_default()).await,
+            None => future::pending().await,
+        }
+    };
+    tokio::pin!(until);
+
+    loop {
+        let event = tokio::select! {
+            event = events.recv() => event,
+            _ = tx.closed() => break,
+            _ = &mut until => break,
+        };
+        let Some((index, event)) = event else {
+            break;
+        };
+        let records: Vec<_> = match event {
+            LogEvent::Records(records) => records
+                .into_iter()
+                .filter(|record| filter.matches(record))
+                .flat_map(|record| match greps.get_mut(index) {
+                    Some(grep) => grep.lines(record),
+                    None => vec![record],
+                })
+                .map(|record| (index, record))
+                .collect(),
+            LogEvent::Dropped(dropped) => vec![(index, dropped_notice(dropped))],
+        };
+        for response in formatter.format(records) {
+            if tx.send(Ok(response)).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Tells a client that is too slow to keep up with the output of a program
+/// how many records it missed.
+fn dropped_notice(dropped: u64) -> LogRecord {
+    LogRecord {
+        stream: LOG_STREAM_STDERR,
+        time: SystemTime::now(),
+        bytes: format!("[wacker: {} records of the output dropped]\n", dropped).into(),
+    }
+}
+
+/// Selects the log records of a stream in a time range.
+#[derive(Clone, Copy)]
+struct LogFilter {
+    /// 0 means all streams.
+    stream: u32,
+    since: Option<SystemTime>,
+    until: Option<SystemTime>,
+}
+
+impl LogFilter {
+    fn new(req: &LogRequest) -> Self {
+        Self {
+            stream: req.stream,
+            since: (req.since > 0).then(|| UNIX_EPOCH + Duration::from_secs(req.since)),
+            until: (req.until > 0).then(|| UNIX_EPOCH + Duration::from_secs(req.until)),
+        }
+    }
+
+    fn matches(&self, record: &LogRecord) -> bool {
+        (self.stream == 0 || record.stream == self.stream)
+            && self.since.map_or(true, |since| record.time >= since)
+            && self.until.map_or(true, |until| record.time < until)
+    }
+}
+
+/// Selects the lines of the logs of a program matching a pattern like grep.
+///
+/// The records are split into lines, and a line written across several records
+/// is put together with the time of its first record.
+#[derive(Clone)]
+struct LogGrep {
+    regex: Regex,
+    invert_match: bool,
+    /// The incomplete last lines of stdout and stderr.
+    pending: [Option<LogRecord>; 2],
+}
+
+impl LogGrep {
+    /// Returns the grep of the request, or `None` if it doesn't filter lines.
+    fn new(req: &LogRequest) -> Result<Option<Self>, Status> {
+        if req.grep.is_empty() {
+            return Ok(None);
+        }
+        let pattern = match req.fixed_strings {
+            true => regex::escape(&req.grep),
+            false => req.grep.clone(),
+        };
+        let regex = Regex::new(&pattern)
+            .map_err(|err| Status::invalid_argument(format!("invalid grep pattern {}: {}", req.grep, err)))?;
+        Ok(Some(Self {
+            regex,
+            invert_match: req.invert_match,
+            pending: [None, None],
+        }))
+    }
+
+    fn matches(&self, line: &[u8]) -> bool {
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        self.regex.is_match(line) != self.invert_match
+    }
+
+    /// Returns the matching lines completed by the record.
+    fn lines(&mut self, record: LogRecord) -> Vec<LogRecord> {
+        let pending = &mut self.pending[(record.stream == LOG_STREAM_STDERR) as usize];
+        let mut lines = vec![];
+        for line in record.bytes.split_inclusive(|byte| *byte == b'\n') {
+            let line = match pending.take() {
+                Some(pending) => LogRecord {
+                    bytes: [pending.bytes.as_ref(), line].concat().into(),
+                    ..pending
+                },
+                None => LogRecord {
+                    stream: record.stream,
+                    time: record.time,
+                    bytes: record.bytes.slice_ref(line),
+                },
+            };
+            match line.bytes.ends_with(b"\n") {
+                true => lines.push(line),
+                false => *pending = Some(line),
+            }
+        }
+        lines.retain(|line| self.matches(&line.bytes));
+        lines
+    }
+
+    /// Returns the incomplete last lines if they match.
+    fn flush(&mut self) -> Vec<LogRecord> {
+        let lines: Vec<_> = self.pending.iter_mut().filter_map(Option::take).collect();
+        lines.into_iter().filter(|line| self.matches(&line.bytes)).collect()
+    }
+}
+
+/// Returns the records of the last `lines` lines, or all the records if
+/// `lines` is 0.
+fn tail(mut records: Vec<LogRecord>, lines: usize) -> Vec<LogRecord> {
+    if lines == 0 {
+        return records;
+    }
+    let mut remaining = lines;
+    // A newline at the very end only terminates the last line.
+    let mut last = true;
+    for i in (0..records.len()).rev() {
+        for pos in (0..records[i].bytes.len()).rev() {
+            if records[i].bytes[pos] == b'\n' && !last {
+                remaining -= 1;
+                if remaining == 0 {
+                    let mut records = records.split_off(i);
+                    records[0].bytes = records[0].bytes.slice(pos + 1..);
+                    return records;
+                }
+            }
+            last = false;
+        }
     }
+    records
 }
 
-fn loop_stream(mut file: File, mut last_position: usize) -> impl Stream<Item = Result<String>> {
-    let mut contents = String::new();
-    let mut interval = time::interval(Duration::from_millis(200));
+/// Converts log records into responses, merging adjacent records of the same
+/// stream, and prefixing every line with the time it was written if asked.
+struct LogFormatter {
+    ids: Vec<String>,
+    timestamps: bool,
+    /// Whether the next record of stdout and stderr of each program starts a
+    /// new line.
+    line_start: Vec<[bool; 2]>,
+}
+
+impl LogFormatter {
+    fn new(ids: Vec<String>, timestamps: bool) -> Self {
+        Self {
+            line_start: vec![[true; 2]; ids.len()],
+            ids,
+            timestamps,
+        }
+    }
 
-    try_stream! {
-        loop {
-            contents.truncate(0);
-            file.seek(SeekFrom::Start(last_position as u64)).await?;
-            last_position += file.read_to_string(&mut contents).await?;
-            if !contents.is_empty() {
-                yield contents.clone();
+    /// Formats the records of the programs, which are tagged with the index
+    /// of their program.
+    fn format(&mut self, records: Vec<(usize, LogRecord)>) -> Vec<LogResponse> {
+        let mut merged: Vec<(usize, u32, Vec<u8>)> = vec![];
+        for (index, record) in records {
+            let bytes = match self.timestamps {
+                true => self.prefix_lines(index, &record),
+                false => record.bytes.to_vec(),
+            };
+            match merged.last_mut() {
+                Some((last, stream, merged)) if *last == index && *stream == record.stream => {
+                    merged.extend_from_slice(&bytes)
+                }
+                _ => merged.push((index, record.stream, bytes)),
             }
+        }
+        merged
+            .into_iter()
+            .filter(|(_, _, bytes)| !bytes.is_empty())
+            .map(|(index, stream, content)| LogResponse {
+                content,
+                stream,
+                id: self.ids[index].clone(),
+            })
+            .collect()
+    }
 
-            interval.tick().await;
+    fn prefix_lines(&mut self, index: usize, record: &LogRecord) -> Vec<u8> {
+        let timestamp = DateTime::<Utc>::from(record.time).to_rfc3339_opts(SecondsFormat::Nanos, true);
+        let line_start = &mut self.line_start[index][(record.stream == LOG_STREAM_STDERR) as usize];
+        let mut bytes = vec![];
+        for line in record.bytes.split_inclusive(|byte| *byte == b'\n') {
+            if *line_start {
+                bytes.extend_from_slice(timestamp.as_bytes());
+                bytes.push(b' ');
+            }
+            bytes.extend_from_slice(line);
+            *line_start = line.ends_with(b"\n");
         }
+        bytes
     }
 }
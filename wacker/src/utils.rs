@@ -1,4 +1,8 @@
+use crate::{LOG_DRIVER_FILE, LOG_DRIVER_JSON_LINES, LOG_DRIVER_SYSLOG};
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Local};
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 pub fn generate_random_string(length: usize) -> String {
     thread_rng()
@@ -8,6 +12,111 @@ pub fn generate_random_string(length: usize) -> String {
         .collect()
 }
 
+/// Parses a human-readable size such as `512K`, `64M` or `1GiB` into bytes.
+///
+/// The units are powers of 1024, and a plain number is a number of bytes.
+pub fn parse_size(size: &str) -> Result<u64> {
+    let size = size.trim();
+    let pos = size.find(|c: char| !c.is_ascii_digit()).unwrap_or(size.len());
+    let (number, unit) = size.split_at(pos);
+    if number.is_empty() {
+        bail!("invalid size {}", size);
+    }
+    let number: u64 = number.parse()?;
+    let shift = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 0,
+        "K" | "KB" | "KIB" => 10,
+        "M" | "MB" | "MIB" => 20,
+        "G" | "GB" | "GIB" => 30,
+        _ => bail!("invalid size unit {} in {}", unit, size),
+    };
+    number
+        .checked_mul(1 << shift)
+        .ok_or(anyhow!("size {} is too large", size))
+}
+
+/// Parses the name of a log driver, `file`, `json-lines` or `syslog`.
+pub fn parse_log_driver(driver: &str) -> Result<u32> {
+    match driver {
+        "file" => Ok(LOG_DRIVER_FILE),
+        "json-lines" => Ok(LOG_DRIVER_JSON_LINES),
+        "syslog" => Ok(LOG_DRIVER_SYSLOG),
+        _ => bail!("invalid log driver {}, expected file, json-lines or syslog", driver),
+    }
+}
+
+/// Formats seconds since the Unix epoch as a local time, e.g.
+/// `2024-01-01 12:00:00`.
+pub fn format_time(secs: u64) -> String {
+    DateTime::from_timestamp(secs as i64, 0)
+        .map(|time| time.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_default()
+}
+
+/// Formats seconds since the Unix epoch as an RFC 3339 time, 0 means unset.
+pub fn format_rfc3339(secs: u64) -> Option<String> {
+    match secs {
+        0 => None,
+        _ => DateTime::from_timestamp(secs as i64, 0).map(|time| time.with_timezone(&Local).to_rfc3339()),
+    }
+}
+
+/// Parses a duration such as `45s`, `10m` or `1h30m`, the units are `s`,
+/// `m`, `h` and `d`.
+pub fn parse_duration(duration: &str) -> Result<Duration> {
+    let mut rest = duration.trim();
+    if rest.is_empty() {
+        bail!("invalid duration {}", duration);
+    }
+    let mut secs: u64 = 0;
+    while !rest.is_empty() {
+        let pos = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        let (number, unit) = rest.split_at(pos);
+        let unit_len = unit.find(|c: char| c.is_ascii_digit()).unwrap_or(unit.len());
+        let (unit, next) = unit.split_at(unit_len);
+        if number.is_empty() {
+            bail!("invalid duration {}", duration);
+        }
+        let number: u64 = number.parse()?;
+        let unit_secs = match unit {
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            _ => bail!("invalid duration unit {:?} in {}", unit, duration),
+        };
+        secs = number
+            .checked_mul(unit_secs)
+            .and_then(|n| secs.checked_add(n))
+            .ok_or(anyhow!("duration {} is too large", duration))?;
+        rest = next;
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+/// Parses a point in time into seconds since the Unix epoch, it can be an
+/// RFC 3339 time, e.g. `2024-01-01T12:00:00Z`, a Unix timestamp, or a
+/// duration before now, e.g. `10m`.
+pub fn parse_time(time: &str) -> Result<u64> {
+    parse_time_at(time, SystemTime::now())
+}
+
+fn parse_time_at(time: &str, now: SystemTime) -> Result<u64> {
+    let time = time.trim();
+    if !time.is_empty() && time.chars().all(|c| c.is_ascii_digit()) {
+        return Ok(time.parse()?);
+    }
+    if let Ok(time) = DateTime::parse_from_rfc3339(time) {
+        return u64::try_from(time.timestamp()).map_err(|_| anyhow!("time {} is before 1970", time));
+    }
+    let duration = parse_duration(time).map_err(|_| anyhow!("invalid time {}", time))?;
+    let time = now
+        .checked_sub(duration)
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .ok_or(anyhow!("time {} is before 1970", time))?;
+    Ok(time.as_secs())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -16,4 +125,44 @@ mod tests {
     fn test_generate_random_string() {
         assert_eq!(generate_random_string(5).len(), 5);
     }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("100").unwrap(), 100);
+        assert_eq!(parse_size("4k").unwrap(), 4 << 10);
+        assert_eq!(parse_size("64M").unwrap(), 64 << 20);
+        assert_eq!(parse_size("1GiB").unwrap(), 1 << 30);
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("10X").is_err());
+        assert!(parse_size("99999999999G").is_err());
+    }
+
+    #[test]
+    fn test_parse_log_driver() {
+        assert_eq!(parse_log_driver("file").unwrap(), LOG_DRIVER_FILE);
+        assert_eq!(parse_log_driver("json-lines").unwrap(), LOG_DRIVER_JSON_LINES);
+        assert_eq!(parse_log_driver("syslog").unwrap(), LOG_DRIVER_SYSLOG);
+        assert!(parse_log_driver("journald").is_err());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("45s").unwrap(), Duration::from_secs(45));
+        assert_eq!(parse_duration("1h30m").unwrap(), Duration::from_secs(90 * 60));
+        assert_eq!(parse_duration("2d").unwrap(), Duration::from_secs(2 * 24 * 60 * 60));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("10").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("10x").is_err());
+    }
+
+    #[test]
+    fn test_parse_time() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert_eq!(parse_time_at("1600000000", now).unwrap(), 1_600_000_000);
+        assert_eq!(parse_time_at("2024-01-01T00:00:00Z", now).unwrap(), 1_704_067_200);
+        assert_eq!(parse_time_at("2024-01-01T08:00:00+08:00", now).unwrap(), 1_704_067_200);
+        assert_eq!(parse_time_at("10m", now).unwrap(), 1_700_000_000 - 600);
+        assert!(parse_time_at("yesterday", now).is_err());
+    }
 }
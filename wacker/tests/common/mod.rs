@@ -6,9 +6,11 @@ use tonic::transport::Channel;
 use wacker::{new_client_with_path, utils::generate_random_string, Client, Server};
 
 pub struct TestServer {
-    sender: Sender<()>,
-    receiver: Receiver<()>,
+    // Sends whether to remove the server directory when shutting down.
+    sender: Sender<bool>,
+    receiver: Receiver<bool>,
     dir: String,
+    ingress_addr: Option<String>,
 }
 
 impl TestServer {
@@ -18,24 +20,46 @@ impl TestServer {
             sender,
             receiver,
             dir: format!("wacker-test-{}", generate_random_string(5)),
+            ingress_addr: None,
         }
     }
 
+    /// Makes the server listen for the routes of programs on the address.
+    pub fn with_ingress(mut self, addr: &str) -> Self {
+        self.ingress_addr = Some(addr.to_string());
+        self
+    }
+
     pub async fn start(&mut self) {
         let dir = self.dir.clone();
         let mut receiver = self.receiver.resubscribe();
 
-        Server::new()
-            .with_dir(dir.clone())
-            .is_test(true)
+        let mut server = Server::new();
+        server.with_dir(dir.clone()).is_test(true);
+        if let Some(addr) = &self.ingress_addr {
+            server.ingress_addr(addr.clone());
+        }
+        server
             .start(async move {
-                receiver.recv().await.expect("");
-                remove_dir_all(dir).expect("remove dir failed");
+                if receiver.recv().await.expect("") {
+                    remove_dir_all(dir).expect("remove dir failed");
+                }
             })
             .await
             .unwrap();
     }
 
+    /// Shuts down the server and starts it again with the same directory.
+    pub async fn restart(&mut self) {
+        let _ = self.sender.send(false);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        self.start().await;
+    }
+
+    pub fn dir(&self) -> &str {
+        &self.dir
+    }
+
     pub async fn client(&self) -> Client<Channel> {
         new_client_with_path(format!("{}/wacker.sock", self.dir)).await.unwrap()
     }
@@ -43,7 +67,7 @@ impl TestServer {
 
 impl Drop for TestServer {
     fn drop(&mut self) {
-        let _ = self.sender.send(());
+        let _ = self.sender.send(true);
         sleep(Duration::from_secs(1));
     }
 }
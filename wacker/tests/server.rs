@@ -2,13 +2,25 @@ mod common;
 
 use crate::common::TestServer;
 use anyhow::Result;
-use reqwest::ClientBuilder;
-use std::time::Duration;
+use reqwest::{tls::TlsInfo, ClientBuilder, StatusCode, Version};
+use serde::Serialize;
+use std::fs::{copy, create_dir, read_to_string, write, File};
+use std::io::BufReader;
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
 use tokio::time::sleep;
-use tokio_stream::StreamExt;
+use tokio_stream::{wrappers::ReceiverStream, StreamExt};
+use tonic::Code;
 use wacker::{
-    DeleteRequest, LogRequest, RestartRequest, RunRequest, ServeRequest, StopRequest, PROGRAM_STATUS_FINISHED,
-    PROGRAM_STATUS_RUNNING, PROGRAM_STATUS_STOPPED,
+    attach_request, attach_response, AttachProgram, AttachRequest, CreateVolumeRequest, DeleteRequest, DirMount,
+    InspectRequest, InspectVolumeRequest, LogConfig, LogRequest, RateLimit, RemoveVolumeRequest, ResourceLimits,
+    RestartPolicy, RestartRequest, Route, RunRequest, ServeRequest, StopRequest, VolumeMount, WaitRequest,
+    LOG_DRIVER_JSON_LINES, LOG_DRIVER_SYSLOG, LOG_STREAM_STDERR, LOG_STREAM_STDOUT, PROGRAM_STATUS_ERROR,
+    PROGRAM_STATUS_FINISHED, PROGRAM_STATUS_FUEL_EXHAUSTED, PROGRAM_STATUS_RESTARTING, PROGRAM_STATUS_RUNNING,
+    PROGRAM_STATUS_STOPPED, PROGRAM_STATUS_TIMED_OUT, PROGRAM_TYPE_CLI, RESTART_POLICY_ALWAYS,
+    RESTART_POLICY_ON_FAILURE,
 };
 
 #[tokio::test(flavor = "multi_thread")]
@@ -21,18 +33,21 @@ async fn run() -> Result<()> {
         .run(RunRequest {
             path: "./tests/wasm/hello.wasm".parse()?,
             args: vec![],
+            ..Default::default()
         })
         .await?;
     client
         .run(RunRequest {
             path: "./tests/wasm/cli.wasm".parse()?,
             args: vec!["-a=b".to_string(), "-c=d".to_string()],
+            ..Default::default()
         })
         .await?;
     client
         .run(RunRequest {
             path: "./tests/wasm/http_client.wasm".parse()?,
             args: vec![],
+            ..Default::default()
         })
         .await?;
     sleep(Duration::from_secs(15)).await;
@@ -45,6 +60,788 @@ async fn run() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn wait() -> Result<()> {
+    let mut server = TestServer::new();
+    server.start().await;
+
+    let mut client = server.client().await;
+    for (path, status, exit_code) in [
+        ("./tests/wasm/hello.wasm", PROGRAM_STATUS_FINISHED, 0),
+        ("./tests/wasm/exit.wasm", PROGRAM_STATUS_ERROR, 3),
+    ] {
+        let response = client
+            .run(RunRequest {
+                path: path.to_string(),
+                ..Default::default()
+            })
+            .await?
+            .into_inner();
+        let response = client.wait(WaitRequest { id: response.id }).await?.into_inner();
+        assert_eq!(response.status, status);
+        assert_eq!(response.exit_code, Some(exit_code));
+    }
+
+    let response = client.list(()).await?.into_inner();
+    let mut exit_codes: Vec<_> = response.programs.iter().map(|program| program.exit_code).collect();
+    exit_codes.sort();
+    assert_eq!(exit_codes, vec![Some(0), Some(3)]);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn attach() -> Result<()> {
+    let mut server = TestServer::new();
+    server.start().await;
+
+    let mut client = server.client().await;
+    for (path, stdin, stdout, stderr, status, exit_code) in [
+        (
+            "./tests/wasm/cat.wasm",
+            vec!["hello\n", "world\n"],
+            "hello\nworld\n",
+            "EOF\n",
+            PROGRAM_STATUS_FINISHED,
+            Some(0),
+        ),
+        ("./tests/wasm/exit.wasm", vec![], "", "", PROGRAM_STATUS_ERROR, Some(3)),
+    ] {
+        let mut requests = vec![AttachRequest {
+            request: Some(attach_request::Request::Run(RunRequest {
+                path: path.to_string(),
+                interactive: true,
+                ..Default::default()
+            })),
+        }];
+        requests.extend(stdin.into_iter().map(|data| AttachRequest {
+            request: Some(attach_request::Request::Stdin(data.as_bytes().to_vec())),
+        }));
+
+        let mut responses = client.attach(tokio_stream::iter(requests)).await?.into_inner();
+        let mut id = None;
+        let mut exit = None;
+        let (mut out, mut err) = (vec![], vec![]);
+        while let Some(response) = responses.next().await {
+            match response?.response {
+                Some(attach_response::Response::Id(program_id)) => id = Some(program_id),
+                Some(attach_response::Response::Stdout(bytes)) => out.extend(bytes),
+                Some(attach_response::Response::Stderr(bytes)) => err.extend(bytes),
+                Some(attach_response::Response::Exit(response)) => exit = Some(response),
+                None => {}
+            }
+        }
+        assert!(id.is_some());
+        assert_eq!(String::from_utf8(out)?, stdout);
+        assert_eq!(String::from_utf8(err)?, stderr);
+        let exit = exit.expect("missing the exit response");
+        assert_eq!(exit.status, status);
+        assert_eq!(exit.exit_code, exit_code);
+    }
+
+    let response = client
+        .attach(tokio_stream::iter(vec![AttachRequest {
+            request: Some(attach_request::Request::Stdin(vec![])),
+        }]))
+        .await;
+    assert_eq!(response.err().map(|status| status.code()), Some(Code::InvalidArgument));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn attach_slow() -> Result<()> {
+    let mut server = TestServer::new();
+    server.start().await;
+
+    for log in [
+        None,
+        Some(LogConfig {
+            driver: LOG_DRIVER_SYSLOG,
+            syslog_address: "/nonexistent/syslog.sock".to_string(),
+            ..Default::default()
+        }),
+    ] {
+        let can_read_logs = log.is_none();
+        let (tx, rx) = mpsc::channel(1);
+        tx.send(AttachRequest {
+            request: Some(attach_request::Request::Run(RunRequest {
+                path: "./tests/wasm/cat.wasm".parse()?,
+                interactive: true,
+                log,
+                ..Default::default()
+            })),
+        })
+        .await?;
+        let mut responses = server
+            .client()
+            .await
+            .attach(ReceiverStream::new(rx))
+            .await?
+            .into_inner();
+        let Some(attach_response::Response::Id(id)) = responses.next().await.expect("missing the ID")?.response else {
+            panic!("missing the ID");
+        };
+
+        // The client doesn't read the output until the program finishes.
+        let mut input = String::new();
+        for i in 0..3000 {
+            let line = format!("{:04}{}\n", i, "x".repeat(4000));
+            tx.send(AttachRequest {
+                request: Some(attach_request::Request::Stdin(line.as_bytes().to_vec())),
+            })
+            .await?;
+            input.push_str(&line);
+        }
+        drop(tx);
+        server.client().await.wait(WaitRequest { id }).await?;
+
+        let (mut out, mut err) = (vec![], vec![]);
+        while let Some(response) = responses.next().await {
+            match response?.response {
+                Some(attach_response::Response::Stdout(bytes)) => out.extend(bytes),
+                Some(attach_response::Response::Stderr(bytes)) => err.extend(bytes),
+                _ => {}
+            }
+        }
+        let err = String::from_utf8(err)?;
+        if can_read_logs {
+            // The missed output is read from the log file.
+            assert!(String::from_utf8(out)? == input);
+            assert_eq!(err, "EOF\n");
+        } else {
+            assert!(out.len() < input.len());
+            assert!(err.contains(" records of the output dropped]\n"));
+        }
+    }
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn attach_running() -> Result<()> {
+    let mut server = TestServer::new();
+    server.start().await;
+
+    let mut client = server.client().await;
+    let id = client
+        .run(RunRequest {
+            path: "./tests/wasm/cat.wasm".parse()?,
+            interactive: true,
+            ..Default::default()
+        })
+        .await?
+        .into_inner()
+        .id;
+
+    // The stdin of the program is kept open after a client goes away, so
+    // it can be written to by the next client.
+    for input in ["hello\n", "world\n"] {
+        let (tx, rx) = mpsc::channel(1);
+        tx.send(AttachRequest {
+            request: Some(attach_request::Request::Program(AttachProgram {
+                id: id.clone(),
+                stdin: true,
+            })),
+        })
+        .await?;
+        let mut responses = client.attach(ReceiverStream::new(rx)).await?.into_inner();
+        tx.send(AttachRequest {
+            request: Some(attach_request::Request::Stdin(input.as_bytes().to_vec())),
+        })
+        .await?;
+
+        let mut output = vec![];
+        while output != input.as_bytes() {
+            match responses.next().await.expect("missing the output")?.response {
+                Some(attach_response::Response::Stdout(bytes)) => output.extend(bytes),
+                Some(attach_response::Response::Id(program_id)) => assert_eq!(program_id, id),
+                response => panic!("unexpected response {:?}", response),
+            }
+        }
+    }
+
+    let response = client.list(()).await?.into_inner();
+    assert_eq!(response.programs[0].status, PROGRAM_STATUS_RUNNING);
+
+    let mut responses = client
+        .attach(tokio_stream::iter(vec![AttachRequest {
+            request: Some(attach_request::Request::Program(AttachProgram {
+                id: id.clone(),
+                stdin: false,
+            })),
+        }]))
+        .await?
+        .into_inner();
+    client.stop(StopRequest { ids: vec![id.clone()] }).await?;
+    let mut exit = None;
+    while let Some(response) = responses.next().await {
+        if let Some(attach_response::Response::Exit(response)) = response?.response {
+            exit = Some(response);
+        }
+    }
+    let exit = exit.expect("missing the exit response");
+    assert_eq!(exit.status, PROGRAM_STATUS_STOPPED);
+    assert_eq!(exit.exit_code, None);
+
+    let id = client
+        .run(RunRequest {
+            path: "./tests/wasm/hello.wasm".parse()?,
+            ..Default::default()
+        })
+        .await?
+        .into_inner()
+        .id;
+    let response = client
+        .attach(tokio_stream::iter(vec![AttachRequest {
+            request: Some(attach_request::Request::Program(AttachProgram { id, stdin: true })),
+        }]))
+        .await;
+    assert_eq!(
+        response.err().map(|status| status.code()),
+        Some(Code::FailedPrecondition)
+    );
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn inspect() -> Result<()> {
+    let mut server = TestServer::new();
+    server.start().await;
+
+    let mut client = server.client().await;
+    let response = client
+        .run(RunRequest {
+            path: "./tests/wasm/memory.wasm".parse()?,
+            args: vec!["-a".to_string()],
+            env: [("KEY".to_string(), "value".to_string())].into(),
+            limits: Some(ResourceLimits {
+                memory: 1 << 20,
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+        .await?
+        .into_inner();
+    sleep(Duration::from_secs(3)).await;
+
+    let response = client
+        .inspect(InspectRequest {
+            ids: vec![response.id.clone()],
+        })
+        .await?
+        .into_inner();
+    assert_eq!(response.programs.len(), 1);
+    let program = &response.programs[0];
+    assert_eq!(program.status, PROGRAM_STATUS_ERROR);
+    assert_eq!(program.args, vec!["-a".to_string()]);
+    assert_eq!(program.env.get("KEY").map(String::as_str), Some("value"));
+    assert_eq!(program.limits.unwrap().memory, 1 << 20);
+    assert!(program.error.contains("memory limit exceeded"));
+    assert!(program.module_hash.starts_with("sha256:"));
+    assert_eq!(
+        program.engine_config.get("consume_fuel").map(String::as_str),
+        Some("true")
+    );
+    assert_eq!(
+        program.engine_config.get("cache_config").map(String::as_str),
+        Some("default")
+    );
+    assert!(program.created_at > 0);
+    assert!(program.finished_at >= program.started_at);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_with_memory_limit() -> Result<()> {
+    let mut server = TestServer::new();
+    server.start().await;
+
+    let mut client = server.client().await;
+    client
+        .run(RunRequest {
+            path: "./tests/wasm/memory.wasm".parse()?,
+            limits: Some(ResourceLimits {
+                memory: 1 << 20,
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+        .await?;
+    sleep(Duration::from_secs(3)).await;
+
+    let response = client.list(()).await?.into_inner();
+    assert_eq!(response.programs[0].status, PROGRAM_STATUS_ERROR);
+
+    let mut response = client
+        .logs(LogRequest {
+            id: "memory".to_string(),
+            follow: false,
+            tail: 0,
+            ..Default::default()
+        })
+        .await?
+        .into_inner();
+    let item = response.next().await.unwrap();
+    assert!(String::from_utf8(item?.content)?.contains("memory limit exceeded"));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_with_fuel() -> Result<()> {
+    let mut server = TestServer::new();
+    server.start().await;
+
+    let mut client = server.client().await;
+    client
+        .run(RunRequest {
+            path: "./tests/wasm/loop.wasm".parse()?,
+            limits: Some(ResourceLimits {
+                fuel: 1_000_000,
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+        .await?;
+    sleep(Duration::from_secs(3)).await;
+
+    let response = client.list(()).await?.into_inner();
+    assert_eq!(response.programs[0].status, PROGRAM_STATUS_FUEL_EXHAUSTED);
+    assert_eq!(response.programs[0].fuel_consumed, 1_000_000);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_with_timeout() -> Result<()> {
+    let mut server = TestServer::new();
+    server.start().await;
+
+    let mut client = server.client().await;
+    client
+        .run(RunRequest {
+            path: "./tests/wasm/loop.wasm".parse()?,
+            timeout: 1,
+            ..Default::default()
+        })
+        .await?;
+    client
+        .run(RunRequest {
+            path: "./tests/wasm/time.wasm".parse()?,
+            timeout: 1,
+            ..Default::default()
+        })
+        .await?;
+    sleep(Duration::from_secs(5)).await;
+
+    let response = client.list(()).await?.into_inner();
+    for program in response.programs {
+        assert_eq!(program.status, PROGRAM_STATUS_TIMED_OUT);
+    }
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_with_restart_policy() -> Result<()> {
+    let mut server = TestServer::new();
+    server.start().await;
+
+    let mut client = server.client().await;
+    let failing = client
+        .run(RunRequest {
+            path: "./tests/wasm/memory.wasm".parse()?,
+            limits: Some(ResourceLimits {
+                memory: 1 << 20,
+                ..Default::default()
+            }),
+            restart: Some(RestartPolicy {
+                policy: RESTART_POLICY_ON_FAILURE,
+                max_retries: 2,
+            }),
+            ..Default::default()
+        })
+        .await?
+        .into_inner();
+    let always = client
+        .run(RunRequest {
+            path: "./tests/wasm/hello.wasm".parse()?,
+            restart: Some(RestartPolicy {
+                policy: RESTART_POLICY_ALWAYS,
+                max_retries: 0,
+            }),
+            ..Default::default()
+        })
+        .await?
+        .into_inner();
+    // Restarted after 1s and 2s.
+    sleep(Duration::from_secs(6)).await;
+
+    let response = client.list(()).await?.into_inner();
+    for program in response.programs {
+        if program.id == failing.id {
+            assert_eq!(program.status, PROGRAM_STATUS_ERROR);
+            assert_eq!(program.restart_count, 2);
+        } else if program.id == always.id {
+            assert!(matches!(
+                program.status,
+                PROGRAM_STATUS_RUNNING | PROGRAM_STATUS_RESTARTING
+            ));
+            assert!(program.restart_count >= 2);
+        }
+    }
+
+    let err = client
+        .run(RunRequest {
+            path: "./tests/wasm/hello.wasm".parse()?,
+            restart: Some(RestartPolicy {
+                policy: RESTART_POLICY_ALWAYS,
+                max_retries: 3,
+            }),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::InvalidArgument);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn resume_after_daemon_restart() -> Result<()> {
+    let mut server = TestServer::new();
+    server.start().await;
+
+    let mut client = server.client().await;
+    let finished = client
+        .run(RunRequest {
+            path: "./tests/wasm/hello.wasm".parse()?,
+            ..Default::default()
+        })
+        .await?
+        .into_inner();
+    let stopped = client
+        .run(RunRequest {
+            path: "./tests/wasm/loop.wasm".parse()?,
+            ..Default::default()
+        })
+        .await?
+        .into_inner();
+    let running = client
+        .run(RunRequest {
+            path: "./tests/wasm/loop.wasm".parse()?,
+            ..Default::default()
+        })
+        .await?
+        .into_inner();
+    sleep(Duration::from_secs(3)).await;
+    client
+        .stop(StopRequest {
+            ids: vec![stopped.id.clone()],
+        })
+        .await?;
+
+    server.restart().await;
+    let mut client = server.client().await;
+    sleep(Duration::from_secs(1)).await;
+
+    let response = client.list(()).await?.into_inner();
+    assert_eq!(response.programs.len(), 3);
+    for program in response.programs {
+        if program.id == finished.id {
+            assert_eq!(program.status, PROGRAM_STATUS_FINISHED);
+        } else if program.id == stopped.id {
+            assert_eq!(program.status, PROGRAM_STATUS_STOPPED);
+        } else if program.id == running.id {
+            assert_eq!(program.status, PROGRAM_STATUS_RUNNING);
+        }
+    }
+
+    // The finished program is not run again.
+    let mut response = client
+        .logs(LogRequest {
+            id: finished.id,
+            follow: false,
+            tail: 0,
+            ..Default::default()
+        })
+        .await?
+        .into_inner();
+    assert_eq!(
+        String::from_utf8(response.next().await.unwrap()?.content)?
+            .matches("Hello")
+            .count(),
+        1
+    );
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn load_legacy_programs() -> Result<()> {
+    // The metadata stored by the daemon before it was versioned.
+    #[derive(Serialize)]
+    struct LegacyProgramMeta {
+        path: String,
+        program_type: u32,
+        addr: Option<String>,
+        args: Vec<String>,
+    }
+
+    let mut server = TestServer::new();
+    {
+        create_dir(server.dir())?;
+        let db = sled::open(format!("{}/db", server.dir()))?;
+        let meta = LegacyProgramMeta {
+            path: "./tests/wasm/hello.wasm".to_string(),
+            program_type: PROGRAM_TYPE_CLI,
+            addr: None,
+            args: vec![],
+        };
+        db.insert("hello-legacy", bincode::serialize(&meta)?)?;
+        db.insert("undecodable", &b"\xff\xff"[..])?;
+        // A state of a version this daemon doesn't know.
+        db.insert("broken-state", bincode::serialize(&meta)?)?;
+        db.open_tree("states")?
+            .insert("broken-state", &b"\xff\xff\xff\xff\xff\xff\xff\xff\x09{}"[..])?;
+        db.flush()?;
+        // The log file held the raw output before the output was recorded.
+        create_dir(format!("{}/logs", server.dir()))?;
+        write(format!("{}/logs/hello-legacy", server.dir()), "legacy output\n")?;
+    }
+    server.start().await;
+
+    let mut client = server.client().await;
+    client
+        .wait(WaitRequest {
+            id: "hello-legacy".to_string(),
+        })
+        .await?;
+    let mut programs = client.list(()).await?.into_inner().programs;
+    programs.sort_by(|a, b| a.id.cmp(&b.id));
+    assert_eq!(programs.len(), 2);
+    // The program is kept with the error of its state.
+    assert_eq!(programs[0].id, "broken-state");
+    assert_eq!(programs[0].status, PROGRAM_STATUS_ERROR);
+    assert_eq!(programs[1].id, "hello-legacy");
+    assert_eq!(programs[1].path, "./tests/wasm/hello.wasm");
+    // The program without a state is run as before states were persisted.
+    assert_eq!(programs[1].status, PROGRAM_STATUS_FINISHED);
+    let response = client
+        .inspect(InspectRequest {
+            ids: vec!["broken-state".to_string()],
+        })
+        .await?
+        .into_inner();
+    assert!(response.programs[0].error.contains("unknown record version 9"));
+    let mut response = client
+        .logs(LogRequest {
+            id: "hello-legacy".to_string(),
+            ..Default::default()
+        })
+        .await?
+        .into_inner();
+    assert_eq!(
+        String::from_utf8(response.next().await.unwrap()?.content)?,
+        "legacy output\nHello, world!\n"
+    );
+    assert!(response.next().await.is_none());
+
+    server.restart().await;
+    let mut client = server.client().await;
+    let response = client.list(()).await?.into_inner();
+    assert_eq!(response.programs.len(), 2);
+    // Its state was persisted once it ran, so it's not run again.
+    let mut response = client
+        .logs(LogRequest {
+            id: "hello-legacy".to_string(),
+            ..Default::default()
+        })
+        .await?
+        .into_inner();
+    assert_eq!(
+        String::from_utf8(response.next().await.unwrap()?.content)?,
+        "legacy output\nHello, world!\n"
+    );
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_with_dirs() -> Result<()> {
+    let mut server = TestServer::new();
+    server.start().await;
+
+    let mut ids = vec![];
+    let mut client = server.client().await;
+    for read_only in [false, true] {
+        let dir = Path::new(server.dir()).join(format!("data-{}", read_only));
+        create_dir(&dir)?;
+        write(dir.join("input.txt"), "Hello, file!\n")?;
+
+        let response = client
+            .run(RunRequest {
+                path: "./tests/wasm/files.wasm".parse()?,
+                dirs: vec![DirMount {
+                    host_path: dir.to_string_lossy().to_string(),
+                    guest_path: "/data".to_string(),
+                    read_only,
+                }],
+                ..Default::default()
+            })
+            .await?
+            .into_inner();
+        ids.push((response.id, dir));
+    }
+    sleep(Duration::from_secs(3)).await;
+
+    for (id, dir) in ids {
+        let mut response = client
+            .logs(LogRequest {
+                id,
+                follow: false,
+                tail: 0,
+                ..Default::default()
+            })
+            .await?
+            .into_inner();
+        let content = String::from_utf8(response.next().await.unwrap()?.content)?;
+
+        if dir.ends_with("data-true") {
+            assert_eq!(content, "Hello, file!\nwrite denied\n");
+            assert!(!dir.join("output.txt").exists());
+        } else {
+            assert_eq!(content, "Hello, file!\n");
+            assert_eq!(read_to_string(dir.join("output.txt"))?, "ok");
+        }
+    }
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_with_env() -> Result<()> {
+    let mut server = TestServer::new();
+    server.start().await;
+
+    let mut ids = vec![];
+    let mut client = server.client().await;
+    for inherit_env in [false, true] {
+        let response = client
+            .run(RunRequest {
+                path: "./tests/wasm/cli.wasm".parse()?,
+                env: [("WACKER_TEST_ENV".to_string(), "hello".to_string())].into(),
+                inherit_env,
+                ..Default::default()
+            })
+            .await?
+            .into_inner();
+        ids.push((response.id, inherit_env));
+    }
+    sleep(Duration::from_secs(3)).await;
+
+    for (id, inherit_env) in ids {
+        let mut response = client
+            .logs(LogRequest {
+                id,
+                follow: false,
+                tail: 0,
+                ..Default::default()
+            })
+            .await?
+            .into_inner();
+        let content = String::from_utf8(response.next().await.unwrap()?.content)?;
+
+        assert!(content.contains("WACKER_TEST_ENV"));
+        assert!(content.contains("hello"));
+        assert_eq!(content.contains("PATH"), inherit_env);
+    }
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn volumes() -> Result<()> {
+    let mut server = TestServer::new();
+    server.start().await;
+
+    let mut client = server.client().await;
+    let volume = client
+        .create_volume(CreateVolumeRequest {
+            name: "data".to_string(),
+        })
+        .await?
+        .into_inner();
+    write(Path::new(&volume.path).join("input.txt"), "Hello, volume!\n")?;
+
+    let err = client
+        .create_volume(CreateVolumeRequest {
+            name: "data".to_string(),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::AlreadyExists);
+    let err = client
+        .create_volume(CreateVolumeRequest {
+            name: "../data".to_string(),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::InvalidArgument);
+
+    let response = client
+        .run(RunRequest {
+            path: "./tests/wasm/files.wasm".parse()?,
+            volumes: vec![VolumeMount {
+                name: "data".to_string(),
+                guest_path: "/data".to_string(),
+                read_only: false,
+            }],
+            ..Default::default()
+        })
+        .await?
+        .into_inner();
+    sleep(Duration::from_secs(3)).await;
+    assert_eq!(read_to_string(Path::new(&volume.path).join("output.txt"))?, "ok");
+
+    let volume = client
+        .inspect_volume(InspectVolumeRequest {
+            name: "data".to_string(),
+        })
+        .await?
+        .into_inner();
+    assert_eq!(volume.programs, vec![response.id.clone()]);
+
+    let err = client
+        .remove_volume(RemoveVolumeRequest {
+            names: vec!["data".to_string()],
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::FailedPrecondition);
+
+    // The volume outlives the program, directories with invalid names are
+    // not listed.
+    client.delete(DeleteRequest { ids: vec![response.id] }).await?;
+    std::fs::create_dir(Path::new(&volume.path).with_file_name(".invalid"))?;
+    let response = client.list_volumes(()).await?.into_inner();
+    assert_eq!(response.volumes.len(), 1);
+    assert!(response.volumes[0].programs.is_empty());
+    assert!(Path::new(&volume.path).join("output.txt").exists());
+
+    client
+        .remove_volume(RemoveVolumeRequest {
+            names: vec!["data".to_string()],
+        })
+        .await?;
+    assert!(client.list_volumes(()).await?.into_inner().volumes.is_empty());
+
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn serve() -> Result<()> {
     let mut server = TestServer::new();
@@ -55,6 +852,7 @@ async fn serve() -> Result<()> {
         .serve(ServeRequest {
             path: "./tests/wasm/http.wasm".parse()?,
             addr: "localhost:8080".to_string(),
+            ..Default::default()
         })
         .await?;
     sleep(Duration::from_secs(1)).await;
@@ -77,6 +875,444 @@ async fn serve() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn serve_http2() -> Result<()> {
+    let mut server = TestServer::new();
+    server.start().await;
+
+    let mut client = server.client().await;
+    for (addr, http2_only) in [("localhost:8083", false), ("localhost:8084", true)] {
+        client
+            .serve(ServeRequest {
+                path: "./tests/wasm/http.wasm".parse()?,
+                addr: addr.to_string(),
+                http2_only,
+                ..Default::default()
+            })
+            .await?;
+    }
+    sleep(Duration::from_secs(1)).await;
+
+    let http1_client = ClientBuilder::new()
+        .timeout(Duration::from_secs(15))
+        .no_proxy()
+        .http1_only()
+        .build()?;
+    let http2_client = ClientBuilder::new()
+        .timeout(Duration::from_secs(15))
+        .no_proxy()
+        .http2_prior_knowledge()
+        .build()?;
+    for (http_client, version) in [(&http1_client, Version::HTTP_11), (&http2_client, Version::HTTP_2)] {
+        let response = http_client.get("http://localhost:8083/api_path").send().await?;
+        assert!(response.status().is_success());
+        assert_eq!(response.version(), version);
+        assert_eq!(response.text().await?, "{\"path\":\"/api_path\",\"query\":{}}");
+    }
+
+    let response = http2_client.get("http://localhost:8084/api_path").send().await?;
+    assert_eq!(response.version(), Version::HTTP_2);
+    assert!(http1_client.get("http://localhost:8084/api_path").send().await.is_err());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn serve_tls() -> Result<()> {
+    let mut server = TestServer::new();
+    server.start().await;
+
+    let cert = format!("{}/cert.pem", server.dir());
+    let key = format!("{}/key.pem", server.dir());
+    copy("./tests/tls/cert1.pem", &cert)?;
+    copy("./tests/tls/key1.pem", &key)?;
+
+    let mut client = server.client().await;
+    assert!(client
+        .serve(ServeRequest {
+            path: "./tests/wasm/http.wasm".parse()?,
+            addr: "localhost:8085".to_string(),
+            tls_cert: cert.clone(),
+            ..Default::default()
+        })
+        .await
+        .is_err());
+    client
+        .serve(ServeRequest {
+            path: "./tests/wasm/http.wasm".parse()?,
+            addr: "localhost:8085".to_string(),
+            tls_cert: cert.clone(),
+            tls_key: key.clone(),
+            ..Default::default()
+        })
+        .await?;
+    sleep(Duration::from_secs(1)).await;
+
+    // Every request opens a new connection, so it performs a new handshake.
+    let http_client = ClientBuilder::new()
+        .timeout(Duration::from_secs(15))
+        .no_proxy()
+        .danger_accept_invalid_certs(true)
+        .tls_info(true)
+        .pool_max_idle_per_host(0)
+        .build()?;
+    let peer_certificate = |response: &reqwest::Response| {
+        response
+            .extensions()
+            .get::<TlsInfo>()
+            .and_then(|info| info.peer_certificate())
+            .map(|cert| cert.to_vec())
+    };
+    let fixture_certificate = |path: &str| -> Result<Vec<u8>> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let cert = rustls_pemfile::certs(&mut reader).next().unwrap()?;
+        Ok(cert.to_vec())
+    };
+
+    let response = http_client.get("https://localhost:8085/api_path").send().await?;
+    assert!(response.status().is_success());
+    assert_eq!(
+        peer_certificate(&response),
+        Some(fixture_certificate("./tests/tls/cert1.pem")?)
+    );
+    assert_eq!(response.text().await?, "{\"path\":\"/api_path\",\"query\":{}}");
+    assert!(http_client.get("http://localhost:8085/api_path").send().await.is_err());
+
+    copy("./tests/tls/cert2.pem", &cert)?;
+    copy("./tests/tls/key2.pem", &key)?;
+    sleep(Duration::from_secs(2)).await;
+
+    let response = http_client.get("https://localhost:8085/api_path").send().await?;
+    assert!(response.status().is_success());
+    assert_eq!(
+        peer_certificate(&response),
+        Some(fixture_certificate("./tests/tls/cert2.pem")?)
+    );
+
+    let id = client.list(()).await?.into_inner().programs[0].id.clone();
+    let response = client.inspect(InspectRequest { ids: vec![id] }).await?.into_inner();
+    assert_eq!(response.programs[0].tls_cert, cert);
+    assert_eq!(response.programs[0].tls_key, key);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn serve_concurrency_limits() -> Result<()> {
+    let mut server = TestServer::new();
+    server.start().await;
+
+    let mut client = server.client().await;
+    assert_eq!(
+        client
+            .serve(ServeRequest {
+                path: "./tests/wasm/http.wasm".parse()?,
+                addr: "localhost:8086".to_string(),
+                request_backlog: 1,
+                ..Default::default()
+            })
+            .await
+            .unwrap_err()
+            .code(),
+        Code::InvalidArgument
+    );
+    for (addr, max_connections, max_concurrent_requests, request_backlog) in [
+        ("localhost:8086", 1, 0, 0),
+        ("localhost:8087", 0, 1, 0),
+        ("localhost:8088", 0, 1, 1),
+    ] {
+        client
+            .serve(ServeRequest {
+                path: "./tests/wasm/http.wasm".parse()?,
+                addr: addr.to_string(),
+                max_connections,
+                max_concurrent_requests,
+                request_backlog,
+                ..Default::default()
+            })
+            .await?;
+    }
+    sleep(Duration::from_secs(1)).await;
+
+    // The only connection is taken, so the request waits until it's closed.
+    let http_client = ClientBuilder::new()
+        .timeout(Duration::from_secs(1))
+        .no_proxy()
+        .pool_max_idle_per_host(0)
+        .build()?;
+    let stream = tokio::net::TcpStream::connect("localhost:8086").await?;
+    sleep(Duration::from_millis(200)).await;
+    assert!(http_client.get("http://localhost:8086/").send().await.is_err());
+    drop(stream);
+    sleep(Duration::from_millis(200)).await;
+    assert!(http_client
+        .get("http://localhost:8086/")
+        .send()
+        .await?
+        .status()
+        .is_success());
+
+    // The client doesn't grant any flow control window, so the response body
+    // is never sent, and the request is in flight until it's dropped.
+    let stalled_client = ClientBuilder::new()
+        .timeout(Duration::from_secs(15))
+        .no_proxy()
+        .http2_prior_knowledge()
+        .http2_initial_stream_window_size(0)
+        .build()?;
+    let http_client = ClientBuilder::new()
+        .timeout(Duration::from_secs(15))
+        .no_proxy()
+        .build()?;
+
+    // Without a backlog, the requests arriving while one is handled are rejected.
+    let response = stalled_client.get("http://localhost:8087/").send().await?;
+    assert!(response.status().is_success());
+    let status = http_client.get("http://localhost:8087/").send().await?.status();
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    drop(response);
+    sleep(Duration::from_millis(200)).await;
+    let status = http_client.get("http://localhost:8087/").send().await?.status();
+    assert!(status.is_success());
+
+    // With a backlog, the requests wait until the one being handled finishes.
+    let response = stalled_client.get("http://localhost:8088/").send().await?;
+    let waiting = tokio::spawn(http_client.get("http://localhost:8088/").send());
+    sleep(Duration::from_millis(200)).await;
+    let status = http_client.get("http://localhost:8088/").send().await?.status();
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert!(!waiting.is_finished());
+    drop(response);
+    assert!(waiting.await??.status().is_success());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn serve_rate_limit() -> Result<()> {
+    let mut server = TestServer::new();
+    server.start().await;
+
+    let mut client = server.client().await;
+    for rate_limit in [
+        RateLimit {
+            burst: 1,
+            ..Default::default()
+        },
+        RateLimit {
+            rate: 1,
+            header: "x api key".to_string(),
+            ..Default::default()
+        },
+    ] {
+        let response = client
+            .serve(ServeRequest {
+                path: "./tests/wasm/http.wasm".parse()?,
+                addr: "localhost:8089".to_string(),
+                rate_limit: Some(rate_limit),
+                ..Default::default()
+            })
+            .await;
+        assert_eq!(response.unwrap_err().code(), Code::InvalidArgument);
+    }
+    for (addr, rate_limit) in [
+        (
+            "localhost:8089",
+            RateLimit {
+                rate: 1,
+                burst: 2,
+                ..Default::default()
+            },
+        ),
+        (
+            "localhost:8090",
+            RateLimit {
+                rate: 1,
+                header: "x-api-key".to_string(),
+                ..Default::default()
+            },
+        ),
+    ] {
+        client
+            .serve(ServeRequest {
+                path: "./tests/wasm/http.wasm".parse()?,
+                addr: addr.to_string(),
+                rate_limit: Some(rate_limit),
+                ..Default::default()
+            })
+            .await?;
+    }
+    sleep(Duration::from_secs(1)).await;
+
+    let http_client = ClientBuilder::new()
+        .timeout(Duration::from_secs(15))
+        .no_proxy()
+        .build()?;
+    for _ in 0..2 {
+        let response = http_client.get("http://localhost:8089/").send().await?;
+        assert!(response.status().is_success());
+    }
+    let response = http_client.get("http://localhost:8089/").send().await?;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers()["retry-after"], "1");
+
+    // The clients are identified by the header if it's present.
+    let request = |key: Option<&str>| {
+        let request = http_client.get("http://localhost:8090/");
+        match key {
+            Some(key) => request.header("x-api-key", key),
+            None => request,
+        }
+        .send()
+    };
+    assert!(request(Some("a")).await?.status().is_success());
+    assert_eq!(request(Some("a")).await?.status(), StatusCode::TOO_MANY_REQUESTS);
+    sleep(Duration::from_secs(1)).await;
+    assert!(request(Some("a")).await?.status().is_success());
+    assert!(request(Some("b")).await?.status().is_success());
+    assert_eq!(request(Some("b")).await?.status(), StatusCode::TOO_MANY_REQUESTS);
+    // A new value of the header takes a token from the bucket of the IP
+    // address, so rotating the values is still limited.
+    for key in ["c", "d", "e"] {
+        assert_eq!(request(Some(key)).await?.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+    assert_eq!(request(None).await?.status(), StatusCode::TOO_MANY_REQUESTS);
+    sleep(Duration::from_secs(1)).await;
+    assert!(request(None).await?.status().is_success());
+    assert!(request(Some("a")).await?.status().is_success());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn serve_ingress() -> Result<()> {
+    let mut server = TestServer::new().with_ingress("127.0.0.1:8091");
+    server.start().await;
+
+    let mut client = server.client().await;
+    let serve = |routes: Vec<(&str, &str)>| ServeRequest {
+        path: "./tests/wasm/http.wasm".to_string(),
+        routes: routes
+            .into_iter()
+            .map(|(host, path)| Route {
+                host: host.to_string(),
+                path: path.to_string(),
+            })
+            .collect(),
+        ..Default::default()
+    };
+    let api = client.serve(serve(vec![("API.local", "/v1")])).await?.into_inner().id;
+    let default = client.serve(serve(vec![("", "/")])).await?.into_inner().id;
+    for routes in [
+        vec![],
+        vec![("api.local", "/v1")],
+        vec![("", "v2")],
+        vec![("api.local:80", "/v2")],
+    ] {
+        let response = client.serve(serve(routes)).await;
+        assert_eq!(response.unwrap_err().code(), Code::InvalidArgument);
+    }
+    sleep(Duration::from_secs(1)).await;
+
+    let response = client.list_routes(()).await?.into_inner();
+    let routes = response
+        .routes
+        .iter()
+        .map(|route| (route.host.as_str(), route.path.as_str(), route.id.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        routes,
+        [("", "/", default.as_str()), ("api.local", "/v1", api.as_str())]
+    );
+
+    // The routes with a host take precedence, and the path prefixes match
+    // whole path segments.
+    let http_client = ClientBuilder::new()
+        .timeout(Duration::from_secs(15))
+        .no_proxy()
+        .build()?;
+    for (host, path) in [
+        ("api.local", "/v1/users"),
+        ("api.local:8091", "/v10"),
+        ("www.local", "/v1/posts"),
+    ] {
+        let response = http_client
+            .get(format!("http://127.0.0.1:8091{}", path))
+            .header("host", host)
+            .send()
+            .await?;
+        assert!(response.status().is_success());
+        assert_eq!(
+            response.text().await?,
+            format!("{{\"path\":\"{}\",\"query\":{{}}}}", path)
+        );
+    }
+    for (id, expected, unexpected) in [
+        (&api, vec!["/v1/users"], vec!["/v10", "/v1/posts"]),
+        (&default, vec!["/v10", "/v1/posts"], vec!["/v1/users"]),
+    ] {
+        let mut response = client
+            .logs(LogRequest {
+                id: id.clone(),
+                ..Default::default()
+            })
+            .await?
+            .into_inner();
+        let mut content = String::new();
+        while let Some(item) = response.next().await {
+            content.push_str(&String::from_utf8(item?.content)?);
+        }
+        for path in expected {
+            assert!(content.contains(&format!("GET to {}\n", path)));
+        }
+        for path in unexpected {
+            assert!(!content.contains(&format!("GET to {}\n", path)));
+        }
+    }
+
+    // The routes of a stopped program are not dispatched anymore.
+    client.stop(StopRequest { ids: vec![default] }).await?;
+    sleep(Duration::from_secs(1)).await;
+    let response = http_client.get("http://127.0.0.1:8091/v1/posts").send().await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn serve_with_fuel() -> Result<()> {
+    let mut server = TestServer::new();
+    server.start().await;
+
+    let mut client = server.client().await;
+    client
+        .serve(ServeRequest {
+            path: "./tests/wasm/http.wasm".parse()?,
+            addr: "localhost:8082".to_string(),
+            limits: Some(ResourceLimits {
+                fuel: 1000,
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+        .await?;
+    sleep(Duration::from_secs(1)).await;
+
+    let http_client = ClientBuilder::new()
+        .timeout(Duration::from_secs(15))
+        .no_proxy()
+        .build()?;
+    let response = http_client.get("http://localhost:8082/").send().await;
+    assert!(response.is_err());
+    sleep(Duration::from_secs(1)).await;
+
+    let response = client.list(()).await?.into_inner();
+    assert_eq!(response.programs[0].status, PROGRAM_STATUS_FUEL_EXHAUSTED);
+    assert_eq!(response.programs[0].fuel_consumed, 1000);
+
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn run_http_path() -> Result<()> {
     let mut server = TestServer::new();
@@ -87,12 +1323,14 @@ async fn run_http_path() -> Result<()> {
         .run(RunRequest {
             path: "https://raw.githubusercontent.com/wacker-dev/wacker/main/wacker/tests/wasm/time.wasm".parse()?,
             args: vec![],
+            ..Default::default()
         })
         .await?;
     client
         .serve(ServeRequest {
             path: "https://raw.githubusercontent.com/wacker-dev/wacker/main/wacker/tests/wasm/http.wasm".parse()?,
             addr: "localhost:8080".to_string(),
+            ..Default::default()
         })
         .await?;
     sleep(Duration::from_secs(10)).await;
@@ -114,6 +1352,7 @@ async fn list() -> Result<()> {
         .run(RunRequest {
             path: "./tests/wasm/hello.wasm".parse()?,
             args: vec![],
+            ..Default::default()
         })
         .await?;
 
@@ -133,6 +1372,7 @@ async fn stop() -> Result<()> {
         .run(RunRequest {
             path: "./tests/wasm/time.wasm".parse()?,
             args: vec![],
+            ..Default::default()
         })
         .await?;
     sleep(Duration::from_secs(1)).await;
@@ -160,6 +1400,7 @@ async fn restart() -> Result<()> {
         .run(RunRequest {
             path: "./tests/wasm/hello.wasm".parse()?,
             args: vec![],
+            ..Default::default()
         })
         .await?
         .into_inner();
@@ -167,6 +1408,7 @@ async fn restart() -> Result<()> {
         .serve(ServeRequest {
             path: "./tests/wasm/http.wasm".parse()?,
             addr: "localhost:8081".to_string(),
+            ..Default::default()
         })
         .await?
         .into_inner();
@@ -192,6 +1434,7 @@ async fn delete() -> Result<()> {
         .run(RunRequest {
             path: "./tests/wasm/hello.wasm".parse()?,
             args: vec![],
+            ..Default::default()
         })
         .await?;
     sleep(Duration::from_secs(1)).await;
@@ -218,12 +1461,14 @@ async fn delete_ambiguous_id() -> Result<()> {
         .run(RunRequest {
             path: "./tests/wasm/hello.wasm".parse()?,
             args: vec![],
+            ..Default::default()
         })
         .await?;
     client
         .run(RunRequest {
             path: "./tests/wasm/hello.wasm".parse()?,
             args: vec![],
+            ..Default::default()
         })
         .await?;
     sleep(Duration::from_secs(1)).await;
@@ -252,6 +1497,7 @@ async fn logs() -> Result<()> {
         .run(RunRequest {
             path: "./tests/wasm/hello.wasm".parse()?,
             args: vec![],
+            ..Default::default()
         })
         .await?;
     sleep(Duration::from_secs(3)).await;
@@ -261,11 +1507,727 @@ async fn logs() -> Result<()> {
             id: "hello".to_string(),
             follow: false,
             tail: 1,
+            ..Default::default()
         })
         .await?
         .into_inner();
-    let item = response.next().await.unwrap();
-    assert_eq!(item.unwrap().content, "Hello, world!\n");
+    let item = response.next().await.unwrap()?;
+    assert_eq!(item.content, b"Hello, world!\n");
+    assert_eq!(item.stream, LOG_STREAM_STDOUT);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn logs_with_time() -> Result<()> {
+    let mut server = TestServer::new();
+    server.start().await;
+
+    let mut client = server.client().await;
+    let started = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let id = client
+        .run(RunRequest {
+            path: "./tests/wasm/hello.wasm".parse()?,
+            ..Default::default()
+        })
+        .await?
+        .into_inner()
+        .id;
+    client.wait(WaitRequest { id: id.clone() }).await?;
+
+    let response = client
+        .logs(LogRequest {
+            id: id.clone(),
+            timestamps: true,
+            ..Default::default()
+        })
+        .await?
+        .into_inner();
+    let items: Vec<_> = response.collect::<Result<_, _>>().await?;
+    assert_eq!(items.len(), 1);
+    let content = String::from_utf8(items[0].content.clone())?;
+    let (timestamp, content) = content.split_once(' ').unwrap();
+    assert_eq!(content, "Hello, world!\n");
+    assert!(chrono::DateTime::parse_from_rfc3339(timestamp)?.timestamp() as u64 >= started);
+
+    for (since, until, count) in [
+        (started, 0, 1),
+        (started + 3600, 0, 0),
+        (0, started, 0),
+        (0, started + 3600, 1),
+    ] {
+        let response = client
+            .logs(LogRequest {
+                id: id.clone(),
+                since,
+                until,
+                ..Default::default()
+            })
+            .await?
+            .into_inner();
+        let items: Vec<_> = response.collect::<Result<_, _>>().await?;
+        assert_eq!(items.len(), count);
+    }
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn logs_follow() -> Result<()> {
+    let mut server = TestServer::new();
+    server.start().await;
+
+    let mut client = server.client().await;
+    let id = client
+        .run(RunRequest {
+            path: "./tests/wasm/cat.wasm".parse()?,
+            interactive: true,
+            ..Default::default()
+        })
+        .await?
+        .into_inner()
+        .id;
+    let mut logs = client
+        .logs(LogRequest {
+            id: id.clone(),
+            follow: true,
+            ..Default::default()
+        })
+        .await?
+        .into_inner();
+
+    // The followers keep receiving the output after the program is restarted.
+    for input in ["hello\n", "world\n"] {
+        let requests = vec![
+            AttachRequest {
+                request: Some(attach_request::Request::Program(AttachProgram {
+                    id: id.clone(),
+                    stdin: true,
+                })),
+            },
+            AttachRequest {
+                request: Some(attach_request::Request::Stdin(input.as_bytes().to_vec())),
+            },
+        ];
+        let _responses = client.attach(tokio_stream::iter(requests)).await?;
+
+        let response = tokio::time::timeout(Duration::from_secs(1), logs.next())
+            .await?
+            .expect("missing the output")?;
+        assert_eq!(response.content, input.as_bytes());
+
+        client.restart(RestartRequest { ids: vec![id.clone()] }).await?;
+    }
+
+    // Following ends once the program is deleted.
+    client.delete(DeleteRequest { ids: vec![id.clone()] }).await?;
+    assert!(tokio::time::timeout(Duration::from_secs(5), logs.next())
+        .await?
+        .is_none());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn logs_follow_slow() -> Result<()> {
+    let mut server = TestServer::new();
+    server.start().await;
+
+    let (tx, rx) = mpsc::channel(1);
+    tx.send(AttachRequest {
+        request: Some(attach_request::Request::Run(RunRequest {
+            path: "./tests/wasm/cat.wasm".parse()?,
+            interactive: true,
+            ..Default::default()
+        })),
+    })
+    .await?;
+    let mut responses = server
+        .client()
+        .await
+        .attach(ReceiverStream::new(rx))
+        .await?
+        .into_inner();
+    let Some(attach_response::Response::Id(id)) = responses.next().await.expect("missing the ID")?.response else {
+        panic!("missing the ID");
+    };
+    let mut logs = server
+        .client()
+        .await
+        .logs(LogRequest {
+            id: id.clone(),
+            follow: true,
+            stream: LOG_STREAM_STDOUT,
+            ..Default::default()
+        })
+        .await?
+        .into_inner();
+
+    // The follower doesn't read the output until the program finishes, which
+    // is far more than it can buffer, so it catches up from the log file.
+    let mut input = String::new();
+    for i in 0..3000 {
+        let line = format!("{:04}{}\n", i, "x".repeat(4000));
+        tx.send(AttachRequest {
+            request: Some(attach_request::Request::Stdin(line.as_bytes().to_vec())),
+        })
+        .await?;
+        input.push_str(&line);
+    }
+    drop(tx);
+    server.client().await.wait(WaitRequest { id }).await?;
+
+    let mut output = vec![];
+    while output.len() < input.len() {
+        let response = tokio::time::timeout(Duration::from_secs(5), logs.next())
+            .await?
+            .expect("missing the output")?;
+        output.extend(response.content);
+    }
+    assert!(String::from_utf8(output)? == input);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn logs_rotation() -> Result<()> {
+    let mut server = TestServer::new();
+    server.start().await;
+
+    let mut client = server.client().await;
+    let mut requests = vec![AttachRequest {
+        request: Some(attach_request::Request::Run(RunRequest {
+            path: "./tests/wasm/cat.wasm".parse()?,
+            interactive: true,
+            log: Some(LogConfig {
+                max_size: 20,
+                max_files: 10,
+                ..Default::default()
+            }),
+            ..Default::default()
+        })),
+    }];
+    requests.extend(["hello\n", "world\n"].into_iter().map(|data| AttachRequest {
+        request: Some(attach_request::Request::Stdin(data.as_bytes().to_vec())),
+    }));
+    let mut responses = client.attach(tokio_stream::iter(requests)).await?.into_inner();
+    let mut id = String::new();
+    while let Some(response) = responses.next().await {
+        if let Some(attach_response::Response::Id(program_id)) = response?.response {
+            id = program_id;
+        }
+    }
+
+    let log_path = Path::new(server.dir()).join("logs").join(&id);
+    assert!(log_path.exists());
+    assert!(Path::new(&format!("{}.1", log_path.display())).exists());
+
+    let response = client
+        .logs(LogRequest {
+            id: id.clone(),
+            ..Default::default()
+        })
+        .await?
+        .into_inner();
+    let items: Vec<_> = response.collect::<Result<_, _>>().await?;
+    let content: Vec<u8> = items.into_iter().flat_map(|item| item.content).collect();
+    assert_eq!(content, b"hello\nworld\nEOF\n");
+
+    let response = client
+        .inspect(InspectRequest { ids: vec![id.clone()] })
+        .await?
+        .into_inner();
+    assert_eq!(
+        response.programs[0].log,
+        Some(LogConfig {
+            max_size: 20,
+            max_files: 10,
+            ..Default::default()
+        })
+    );
+
+    client.delete(DeleteRequest { ids: vec![id.clone()] }).await?;
+    let remaining = std::fs::read_dir(Path::new(server.dir()).join("logs"))?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with(&id))
+        .count();
+    assert_eq!(remaining, 0);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn logs_rotation_max_size_only() -> Result<()> {
+    let mut server = TestServer::new();
+    server.start().await;
+
+    let mut client = server.client().await;
+    let mut requests = vec![AttachRequest {
+        request: Some(attach_request::Request::Run(RunRequest {
+            path: "./tests/wasm/cat.wasm".parse()?,
+            interactive: true,
+            log: Some(LogConfig {
+                max_size: 20,
+                ..Default::default()
+            }),
+            ..Default::default()
+        })),
+    }];
+    requests.extend(["hello\n", "world\n"].into_iter().map(|data| AttachRequest {
+        request: Some(attach_request::Request::Stdin(data.as_bytes().to_vec())),
+    }));
+    let mut responses = client.attach(tokio_stream::iter(requests)).await?.into_inner();
+    let mut id = String::new();
+    while let Some(response) = responses.next().await {
+        if let Some(attach_response::Response::Id(program_id)) = response?.response {
+            id = program_id;
+        }
+    }
+
+    // One rotated segment is kept by default.
+    let log_path = Path::new(server.dir()).join("logs").join(&id);
+    assert!(Path::new(&format!("{}.1", log_path.display())).exists());
+    assert!(!Path::new(&format!("{}.2", log_path.display())).exists());
+
+    let response = client
+        .logs(LogRequest {
+            id: id.clone(),
+            ..Default::default()
+        })
+        .await?
+        .into_inner();
+    let items: Vec<_> = response.collect::<Result<_, _>>().await?;
+    let content: Vec<u8> = items.into_iter().flat_map(|item| item.content).collect();
+    assert_eq!(String::from_utf8(content)?, "world\nEOF\n");
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn logs_binary() -> Result<()> {
+    let mut server = TestServer::new();
+    server.start().await;
+
+    let mut client = server.client().await;
+    let requests = vec![
+        AttachRequest {
+            request: Some(attach_request::Request::Run(RunRequest {
+                path: "./tests/wasm/cat.wasm".parse()?,
+                interactive: true,
+                ..Default::default()
+            })),
+        },
+        AttachRequest {
+            request: Some(attach_request::Request::Stdin(b"\xff\xfe\n\x00\xc3(\n".to_vec())),
+        },
+    ];
+    let mut responses = client.attach(tokio_stream::iter(requests)).await?.into_inner();
+    let mut id = String::new();
+    while let Some(response) = responses.next().await {
+        if let Some(attach_response::Response::Id(program_id)) = response?.response {
+            id = program_id;
+        }
+    }
+
+    for (tail, timestamps, expected) in [
+        (0, false, &b"\xff\xfe\n\x00\xc3(\n"[..]),
+        (1, false, &b"\x00\xc3(\n"[..]),
+        (2, true, &b"\xff\xfe\n\x00\xc3(\n"[..]),
+    ] {
+        let response = client
+            .logs(LogRequest {
+                id: id.clone(),
+                tail,
+                stream: LOG_STREAM_STDOUT,
+                timestamps,
+                ..Default::default()
+            })
+            .await?
+            .into_inner();
+        let items: Vec<_> = response.collect::<Result<_, _>>().await?;
+        let content: Vec<u8> = items.into_iter().flat_map(|item| item.content).collect();
+        match timestamps {
+            // Every line is prefixed with a timestamp and a space.
+            true => {
+                let lines: Vec<_> = content
+                    .split_inclusive(|byte| *byte == b'\n')
+                    .map(|line| line.splitn(2, |byte| *byte == b' ').nth(1).unwrap().to_vec())
+                    .collect();
+                assert_eq!(lines.concat(), expected);
+            }
+            false => assert_eq!(content, expected),
+        }
+    }
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn logs_grep() -> Result<()> {
+    let mut server = TestServer::new();
+    server.start().await;
+
+    let mut client = server.client().await;
+    let id = client
+        .run(RunRequest {
+            path: "./tests/wasm/cat.wasm".parse()?,
+            interactive: true,
+            ..Default::default()
+        })
+        .await?
+        .into_inner()
+        .id;
+    let (tx, rx) = mpsc::channel(4);
+    tx.send(AttachRequest {
+        request: Some(attach_request::Request::Program(AttachProgram {
+            id: id.clone(),
+            stdin: true,
+        })),
+    })
+    .await?;
+    let _responses = client.attach(ReceiverStream::new(rx)).await?;
+    // A line may be written in several parts.
+    for input in ["apple\nban", "ana\na.b\n", "cherry\ndate"] {
+        tx.send(AttachRequest {
+            request: Some(attach_request::Request::Stdin(input.as_bytes().to_vec())),
+        })
+        .await?;
+        sleep(Duration::from_millis(200)).await;
+    }
+
+    for (grep, fixed_strings, invert_match, tail, expected) in [
+        ("an", false, false, 0, "banana\n"),
+        ("a.p", false, false, 0, "apple\n"),
+        ("a.p", true, false, 0, ""),
+        ("a.b", true, false, 0, "a.b\n"),
+        ("an", false, true, 0, "apple\na.b\ncherry\ndate"),
+        ("^(c|d)", false, false, 1, "date"),
+    ] {
+        let response = client
+            .logs(LogRequest {
+                id: id.clone(),
+                tail,
+                grep: grep.to_string(),
+                fixed_strings,
+                invert_match,
+                ..Default::default()
+            })
+            .await?
+            .into_inner();
+        let items: Vec<_> = response.collect::<Result<_, _>>().await?;
+        let content: Vec<u8> = items.into_iter().flat_map(|item| item.content).collect();
+        assert_eq!(String::from_utf8(content)?, expected);
+    }
+
+    // The incomplete last line is completed by the output written later.
+    let mut logs = client
+        .logs(LogRequest {
+            id: id.clone(),
+            follow: true,
+            grep: "^d|x".to_string(),
+            ..Default::default()
+        })
+        .await?
+        .into_inner();
+    for input in ["s\nfoo\n", "a", "x\n"] {
+        tx.send(AttachRequest {
+            request: Some(attach_request::Request::Stdin(input.as_bytes().to_vec())),
+        })
+        .await?;
+    }
+    for expected in ["dates\n", "ax\n"] {
+        let response = tokio::time::timeout(Duration::from_secs(1), logs.next())
+            .await?
+            .expect("missing the output")?;
+        assert_eq!(response.content, expected.as_bytes());
+    }
+
+    let response = client
+        .logs(LogRequest {
+            id,
+            grep: "(".to_string(),
+            ..Default::default()
+        })
+        .await;
+    assert_eq!(response.err().map(|status| status.code()), Some(Code::InvalidArgument));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn logs_multiple() -> Result<()> {
+    let mut server = TestServer::new();
+    server.start().await;
+
+    let mut client = server.client().await;
+    let mut ids = vec![];
+    for labels in [vec![("app", "demo"), ("tier", "web")], vec![("app", "demo")], vec![]] {
+        let id = client
+            .run(RunRequest {
+                path: "./tests/wasm/cat.wasm".parse()?,
+                interactive: true,
+                labels: labels
+                    .into_iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect(),
+                ..Default::default()
+            })
+            .await?
+            .into_inner()
+            .id;
+        ids.push(id);
+    }
+    let response = client
+        .inspect(InspectRequest {
+            ids: vec![ids[0].clone()],
+        })
+        .await?
+        .into_inner();
+    assert_eq!(response.programs[0].labels.get("tier").map(String::as_str), Some("web"));
+
+    for (labels, expected) in [
+        (vec![("app", "demo")], vec![ids[0].clone(), ids[1].clone()]),
+        // The output written above is received first.
+        (
+            vec![("app", "demo"), ("tier", "web")],
+            vec![ids[0].clone(), ids[0].clone()],
+        ),
+    ] {
+        let mut logs = client
+            .logs(LogRequest {
+                follow: true,
+                labels: labels
+                    .into_iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect(),
+                ..Default::default()
+            })
+            .await?
+            .into_inner();
+
+        // Write to every program, the followers only receive the output of
+        // the selected ones.
+        for id in &ids {
+            let requests = vec![
+                AttachRequest {
+                    request: Some(attach_request::Request::Program(AttachProgram {
+                        id: id.clone(),
+                        stdin: true,
+                    })),
+                },
+                AttachRequest {
+                    request: Some(attach_request::Request::Stdin(format!("{}\n", id).into_bytes())),
+                },
+            ];
+            let _responses = client.attach(tokio_stream::iter(requests)).await?;
+            sleep(Duration::from_millis(200)).await;
+        }
+        let mut received = vec![];
+        for _ in 0..expected.len() {
+            let response = tokio::time::timeout(Duration::from_secs(1), logs.next())
+                .await?
+                .expect("missing the output")?;
+            assert_eq!(response.content, format!("{}\n", response.id).into_bytes());
+            received.push(response.id);
+        }
+        received.sort();
+        let mut expected = expected;
+        expected.sort();
+        assert_eq!(received, expected);
+        assert!(tokio::time::timeout(Duration::from_millis(500), logs.next())
+            .await
+            .is_err());
+    }
+
+    // The logs written so far are merged by time, the programs were written
+    // to in turn.
+    let response = client
+        .logs(LogRequest {
+            id: ids[2].clone(),
+            ids: vec![ids[0].clone()],
+            ..Default::default()
+        })
+        .await?
+        .into_inner();
+    let items: Vec<_> = response.collect::<Result<_, _>>().await?;
+    let items: Vec<_> = items.into_iter().map(|item| (item.id, item.content)).collect();
+    let expected: Vec<_> = [&ids[0], &ids[2], &ids[0], &ids[2]]
+        .into_iter()
+        .map(|id| (id.clone(), format!("{}\n", id).into_bytes()))
+        .collect();
+    assert_eq!(items, expected);
+
+    let response = client
+        .logs(LogRequest {
+            labels: [("app".to_string(), "none".to_string())].into(),
+            ..Default::default()
+        })
+        .await;
+    assert_eq!(response.err().map(|status| status.code()), Some(Code::NotFound));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn log_drivers() -> Result<()> {
+    let mut server = TestServer::new();
+    server.start().await;
+
+    let mut client = server.client().await;
+    let id = client
+        .run(RunRequest {
+            path: "./tests/wasm/hello.wasm".parse()?,
+            log: Some(LogConfig {
+                driver: LOG_DRIVER_JSON_LINES,
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+        .await?
+        .into_inner()
+        .id;
+    client.wait(WaitRequest { id: id.clone() }).await?;
+
+    let path = Path::new(server.dir()).join("logs").join(format!("{}.json", id));
+    let line: serde_json::Value = serde_json::from_str(read_to_string(&path)?.trim_end())?;
+    assert_eq!(line["id"], id);
+    assert_eq!(line["stream"], "stdout");
+    assert_eq!(line["log"], "Hello, world!\n");
+    assert!(line["time"].is_string());
+
+    // The logs of the json-lines driver are read back from the lines.
+    let mut response = client
+        .logs(LogRequest {
+            id: id.clone(),
+            timestamps: true,
+            ..Default::default()
+        })
+        .await?
+        .into_inner();
+    let response = response.next().await.unwrap()?;
+    assert_eq!(response.stream, LOG_STREAM_STDOUT);
+    assert_eq!(
+        String::from_utf8(response.content)?,
+        format!("{} Hello, world!\n", line["time"].as_str().unwrap())
+    );
+
+    client.delete(DeleteRequest { ids: vec![id] }).await?;
+    assert!(!path.exists());
+
+    let address = Path::new(server.dir()).join("syslog.sock");
+    let socket = UnixDatagram::bind(&address)?;
+    socket.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let id = client
+        .run(RunRequest {
+            path: "./tests/wasm/hello.wasm".parse()?,
+            log: Some(LogConfig {
+                driver: LOG_DRIVER_SYSLOG,
+                syslog_address: address.to_str().unwrap().to_string(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+        .await?
+        .into_inner()
+        .id;
+    let mut buf = [0; 1024];
+    let len = socket.recv(&mut buf)?;
+    let message = String::from_utf8(buf[..len].to_vec())?;
+    assert!(message.starts_with("<14>"));
+    assert!(message.ends_with(&format!(" wacker/{}: Hello, world!", id)));
+
+    // The logs of the syslog driver can't be read back.
+    let response = client
+        .logs(LogRequest {
+            id: id.clone(),
+            ..Default::default()
+        })
+        .await;
+    assert_eq!(
+        response.err().map(|status| status.code()),
+        Some(Code::FailedPrecondition)
+    );
+
+    let response = client
+        .run(RunRequest {
+            path: "./tests/wasm/hello.wasm".parse()?,
+            log: Some(LogConfig {
+                driver: 4,
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+        .await;
+    assert!(response.is_err());
+
+    // The program runs even if syslog is not available.
+    let id = client
+        .run(RunRequest {
+            path: "./tests/wasm/hello.wasm".parse()?,
+            log: Some(LogConfig {
+                driver: LOG_DRIVER_SYSLOG,
+                syslog_address: "/nonexistent/syslog.sock".to_string(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+        .await?
+        .into_inner()
+        .id;
+    let response = client.wait(WaitRequest { id }).await?.into_inner();
+    assert_eq!(response.status, PROGRAM_STATUS_FINISHED);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn logs_by_stream() -> Result<()> {
+    let mut server = TestServer::new();
+    server.start().await;
+
+    let mut client = server.client().await;
+    let id = client
+        .run(RunRequest {
+            path: "./tests/wasm/cat.wasm".parse()?,
+            ..Default::default()
+        })
+        .await?
+        .into_inner()
+        .id;
+    client.wait(WaitRequest { id: id.clone() }).await?;
+
+    for (stream, expected) in [
+        (0, vec![(LOG_STREAM_STDERR, "EOF\n")]),
+        (LOG_STREAM_STDOUT, vec![]),
+        (LOG_STREAM_STDERR, vec![(LOG_STREAM_STDERR, "EOF\n")]),
+    ] {
+        let response = client
+            .logs(LogRequest {
+                id: id.clone(),
+                stream,
+                ..Default::default()
+            })
+            .await?
+            .into_inner();
+        let items: Vec<_> = response
+            .map(|item| item.map(|item| (item.stream, item.content)))
+            .collect::<Result<_, _>>()
+            .await?;
+        let expected: Vec<_> = expected
+            .into_iter()
+            .map(|(stream, content)| (stream, content.as_bytes().to_vec()))
+            .collect();
+        assert_eq!(items, expected);
+    }
+
+    let response = client
+        .logs(LogRequest {
+            id,
+            stream: 3,
+            ..Default::default()
+        })
+        .await;
+    assert_eq!(response.err().map(|status| status.code()), Some(Code::InvalidArgument));
 
     Ok(())
 }